@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use powerfx::{Column, ColumnarTable, DataValue, PowerFxEngine, Record, Session};
+
+
+fn table(rows: usize) -> DataValue {
+    DataValue::Table((0..rows).map(|i| Record::from(vec![
+        ("Id".into(), DataValue::Number(i as f64)),
+        ("Value".into(), DataValue::Number((i % 100) as f64)),
+        ("Name".into(), DataValue::Text(format!("Row {}", i).into())),
+    ])).collect())
+}
+
+fn columnar_table(rows: usize) -> DataValue {
+    DataValue::Table(ColumnarTable::new(vec![
+        ("Id".into(), Column::Number((0..rows).map(|i| Some(i as f64)).collect())),
+        ("Value".into(), Column::Number((0..rows).map(|i| Some((i % 100) as f64)).collect())),
+        ("Name".into(), Column::Text((0..rows).map(|i| Some(format!("Row {}", i).into())).collect())),
+    ]).unwrap().into())
+}
+
+/// A session holding a 100k row table, plus a few large tables that the benchmarked formulas never read.
+fn session() -> Session {
+    let mut session = Session::new();
+    session.set_variable("big", table(100_000));
+    session.set_variable("big_columnar", columnar_table(100_000));
+    for i in 0..4 {
+        session.set_variable(&format!("other{}", i), table(10_000));
+    }
+    session
+}
+
+fn row_scope(c: &mut Criterion) {
+    let session = session();
+    let row = Record::from(vec![("Value".into(), DataValue::Number(1.0))]);
+
+    let mut group = c.benchmark_group("row_scope");
+    group.bench_function("from_record_with_context", |b| b.iter(|| {
+        black_box(Session::from_record_with_context(&row, &session).get_variable("Value").cloned())
+    }));
+    group.bench_function("with_record", |b| {
+        let mut session = session.clone();
+        b.iter(|| black_box(session.with_record(&row, |row_session| row_session.get_variable("Value").cloned())))
+    });
+    group.finish();
+}
+
+fn aggregation(c: &mut Criterion) {
+    let engine = PowerFxEngine::new();
+    let mut session = session();
+
+    let mut group = c.benchmark_group("aggregation");
+    group.sample_size(10);
+    group.bench_function("sum_100k_rows", |b| b.iter(|| black_box(engine.evaluate("Sum(big, Value)", Some(&mut session)).unwrap())));
+    group.bench_function("filter_100k_rows", |b| b.iter(|| black_box(engine.evaluate("Filter(big, Value > 50)", Some(&mut session)).unwrap())));
+    group.bench_function("sum_100k_columnar", |b| b.iter(|| black_box(engine.evaluate("Sum(big_columnar, Value)", Some(&mut session)).unwrap())));
+    group.bench_function("filter_100k_columnar", |b| b.iter(|| black_box(engine.evaluate("Filter(big_columnar, Value > 50)", Some(&mut session)).unwrap())));
+    group.finish();
+}
+
+criterion_group!(benches, row_scope, aggregation);
+criterion_main!(benches);
@@ -2,7 +2,7 @@ extern crate powerfx;
 
 use std::sync::Arc;
 
-use powerfx::{DataValue, EvaluationError, Expression, ExpressionEvaluator, PowerFxEngine, ScalarFunction, Session};
+use powerfx::{DataValue, EvaluationError, Expression, ExpressionEvaluator, PowerFxEngine, ScalarFunction, Session, Signature, ValueType};
 
 
 fn main() {
@@ -13,6 +13,12 @@ fn main() {
 
     let result = engine.evaluate("MyFunction(2, 3)", None).unwrap();
     println!("{:?}", result);
+
+    // Simple functions can be registered from a closure instead, with the arguments converted automatically
+    engine.register_fn("Discount", |price: f64, pct: f64| price * (1.0 - pct / 100.0));
+
+    let result = engine.evaluate("Discount(200, 25)", None).unwrap();
+    println!("{:?}", result);
 }
 
 // Define the custom function
@@ -31,10 +37,6 @@ impl MyFunction {
 impl ScalarFunction for MyFunction {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
-        }
-
         let value1 = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Number(n) => n,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", args[0]))),
@@ -47,4 +49,12 @@ impl ScalarFunction for MyFunction {
 
         Ok(DataValue::Number(value1 + value2))
     }
+
+    // Describe the function, so calls with the wrong number of arguments are rejected before `call`
+    fn signature(&self) -> Signature {
+        Signature::new("Adds two numbers.")
+            .param("a", ValueType::Number)
+            .param("b", ValueType::Number)
+            .returns(ValueType::Number)
+    }
 }
\ No newline at end of file
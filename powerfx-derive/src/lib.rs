@@ -0,0 +1,160 @@
+//! Derive macros for the `powerfx` crate. Use them through `powerfx` with the `derive` feature enabled.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+
+/// Exposes a struct with named fields to formulas as a record.
+///
+/// Generates `PowerFxValue` and `PowerFxRecord` implementations, `From<T> for DataValue` and
+/// `TryFrom<DataValue> for T`. Each field must implement `PowerFxValue`, so fields can be other records, and
+/// `Vec` fields become tables.
+///
+/// Field names are used as they are, unless renamed:
+///
+/// - `#[powerfx(rename_all = "PascalCase")]` or `"camelCase"` on the struct renames every field.
+/// - `#[powerfx(rename = "Order Total")]` on a field renames that field.
+#[proc_macro_derive(PowerFxRecord, attributes(powerfx))]
+pub fn derive_powerfx_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+enum RenameRule {
+    None,
+    PascalCase,
+    CamelCase,
+}
+
+impl RenameRule {
+    fn apply(&self, name: &str) -> String {
+        let words = name.split('_').filter(|w| !w.is_empty());
+        match self {
+            RenameRule::None => name.to_string(),
+            RenameRule::PascalCase => words.map(capitalize).collect(),
+            RenameRule::CamelCase => words.enumerate().map(|(i, w)| if i == 0 { w.to_string() } else { capitalize(w) }).collect(),
+        }
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut rename_rule = RenameRule::None;
+    for attr in &input.attrs {
+        if !attr.path().is_ident("powerfx") {
+            continue;
+        }
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename_all") {
+                let rule: LitStr = meta.value()?.parse()?;
+                rename_rule = match rule.value().as_str() {
+                    "PascalCase" => RenameRule::PascalCase,
+                    "camelCase" => RenameRule::CamelCase,
+                    _ => return Err(meta.error("expected \"PascalCase\" or \"camelCase\"")),
+                };
+                Ok(())
+            } else {
+                Err(meta.error("unsupported powerfx attribute"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new(Span::call_site(), "PowerFxRecord can only be derived for structs with named fields")),
+        },
+        _ => return Err(syn::Error::new(Span::call_site(), "PowerFxRecord can only be derived for structs")),
+    };
+
+    let mut idents = Vec::new();
+    let mut types = Vec::new();
+    let mut field_names = Vec::new();
+    for field in fields {
+        let ident = field.ident.clone().unwrap();
+        let mut field_name = rename_rule.apply(&ident.to_string());
+        for attr in &field.attrs {
+            if !attr.path().is_ident("powerfx") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    let rename: LitStr = meta.value()?.parse()?;
+                    field_name = rename.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported powerfx attribute"))
+                }
+            })?;
+        }
+        idents.push(ident);
+        types.push(field.ty.clone());
+        field_names.push(field_name);
+    }
+
+    let expected_record = format!("Expected a {} record, found {{:?}}", name);
+
+    Ok(quote! {
+        impl #impl_generics ::powerfx::PowerFxRecord for #name #ty_generics #where_clause {
+            fn schema() -> ::powerfx::Schema {
+                ::powerfx::Schema::new(::std::vec![
+                    #( (::std::sync::Arc::from(#field_names), <#types as ::powerfx::PowerFxValue>::data_type()), )*
+                ])
+            }
+        }
+
+        impl #impl_generics ::powerfx::PowerFxValue for #name #ty_generics #where_clause {
+            fn data_type() -> ::powerfx::DataType {
+                ::powerfx::DataType::Record(::std::sync::Arc::new(<Self as ::powerfx::PowerFxRecord>::schema()))
+            }
+
+            fn into_data_value(self) -> ::powerfx::DataValue {
+                ::powerfx::DataValue::Record(::powerfx::Record::from(::std::vec![
+                    #( (::std::sync::Arc::from(#field_names), ::powerfx::PowerFxValue::into_data_value(self.#idents)), )*
+                ]))
+            }
+
+            fn from_data_value(value: ::powerfx::DataValue) -> ::std::result::Result<Self, ::powerfx::EvaluationError> {
+                let record = match value {
+                    ::powerfx::DataValue::Record(r) => r,
+                    v => return ::std::result::Result::Err(::powerfx::EvaluationError::InvalidArgument(::std::format!(#expected_record, v))),
+                };
+                ::std::result::Result::Ok(Self {
+                    #(
+                        #idents: <#types as ::powerfx::PowerFxValue>::from_data_value(record.get(#field_names).cloned().unwrap_or(::powerfx::DataValue::Blank))
+                            .map_err(|e| ::powerfx::EvaluationError::InvalidArgument(::std::format!("field `{}`: {}", #field_names, e)))?,
+                    )*
+                })
+            }
+        }
+
+        impl #impl_generics ::std::convert::From<#name #ty_generics> for ::powerfx::DataValue #where_clause {
+            fn from(value: #name #ty_generics) -> Self {
+                ::powerfx::PowerFxValue::into_data_value(value)
+            }
+        }
+
+        impl #impl_generics ::std::convert::TryFrom<::powerfx::DataValue> for #name #ty_generics #where_clause {
+            type Error = ::powerfx::EvaluationError;
+
+            fn try_from(value: ::powerfx::DataValue) -> ::std::result::Result<Self, Self::Error> {
+                ::powerfx::PowerFxValue::from_data_value(value)
+            }
+        }
+    })
+}
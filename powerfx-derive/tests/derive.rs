@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use powerfx::{DataType, DataValue, EvaluationError, PowerFxEngine, PowerFxRecord, Record, Schema, Session};
+
+
+#[derive(Debug, Clone, PartialEq, PowerFxRecord)]
+#[powerfx(rename_all = "PascalCase")]
+struct Line {
+    product_name: String,
+    quantity: u32,
+    price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, PowerFxRecord)]
+#[powerfx(rename_all = "PascalCase")]
+struct Order {
+    id: i64,
+    #[powerfx(rename = "Order Date")]
+    date: NaiveDate,
+    note: Option<String>,
+    lines: Vec<Line>,
+    tags: Vec<String>,
+}
+
+fn order() -> Order {
+    Order {
+        id: 42,
+        date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+        note: None,
+        lines: vec![
+            Line { product_name: "Foo".to_string(), quantity: 2, price: 2.5 },
+            Line { product_name: "Bar".to_string(), quantity: 1, price: 10.0 },
+        ],
+        tags: vec!["urgent".to_string()],
+    }
+}
+
+#[test]
+fn converts_to_and_from_data_value() {
+    let value: DataValue = order().into();
+
+    let record = match &value {
+        DataValue::Record(r) => r.clone(),
+        _ => panic!("Expected a record"),
+    };
+    assert_eq!(record.get("Id"), Some(&DataValue::Number(42.0)));
+    assert_eq!(record.get("Order Date"), Some(&DataValue::Date(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap())));
+    assert_eq!(record.get("Note"), Some(&DataValue::Blank));
+
+    let tags = match record.get("Tags") {
+        Some(DataValue::Table(t)) => t.clone(),
+        _ => panic!("Expected a table"),
+    };
+    assert_eq!(tags.first(), Some(Record::from(vec![("Value".into(), DataValue::Text("urgent".into()))])));
+
+    assert_eq!(Order::try_from(value).unwrap(), order());
+}
+
+#[test]
+fn records_are_usable_in_formulas() {
+    let engine = PowerFxEngine::new();
+    let mut session = Session::new();
+    session.set_variable("lines", match order().into() {
+        DataValue::Record(r) => r.get("Lines").cloned().unwrap(),
+        _ => panic!("Expected a record"),
+    });
+
+    let result = engine.evaluate("Sum(lines, Quantity * Price)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(15.0));
+
+    let result = engine.evaluate("First(Filter(lines, Price > 5))", Some(&mut session)).unwrap();
+    assert_eq!(Line::try_from(result).unwrap(), Line { product_name: "Bar".to_string(), quantity: 1, price: 10.0 });
+}
+
+#[test]
+fn reports_mismatched_fields() {
+    let value = DataValue::Record(Record::from(vec![
+        ("ProductName".into(), DataValue::Text("Foo".into())),
+        ("Quantity".into(), DataValue::Text("two".into())),
+        ("Price".into(), DataValue::Number(1.0)),
+    ]));
+    let error = Line::try_from(value).unwrap_err();
+    assert!(matches!(&error, EvaluationError::InvalidArgument(m) if m.starts_with("field `Quantity`: Expected a number, found Text(")), "{}", error);
+
+    let value = DataValue::Record(Record::from(vec![
+        ("Id".into(), DataValue::Number(1.0)),
+        ("Order Date".into(), DataValue::Number(2.0)),
+    ]));
+    let error = Order::try_from(value).unwrap_err();
+    assert!(error.to_string().starts_with("field `Order Date`: Expected a date"), "{}", error);
+
+    assert!(Line::try_from(DataValue::Number(1.0)).is_err());
+}
+
+#[test]
+fn exposes_a_static_schema() {
+    let lines = Schema::new(vec![
+        (Arc::from("ProductName"), DataType::Text),
+        (Arc::from("Quantity"), DataType::Number),
+        (Arc::from("Price"), DataType::Number),
+    ]);
+    assert_eq!(Line::schema(), lines);
+
+    let schema = Order::schema();
+    assert_eq!(schema.get("Order Date"), Some(&DataType::Date));
+    assert_eq!(schema.get("Note"), Some(&DataType::Text));
+    assert_eq!(schema.get("Lines"), Some(&DataType::Table(Arc::new(lines))));
+    assert_eq!(schema.get("Tags"), Some(&DataType::Table(Arc::new(Schema::new(vec![(Arc::from("Value"), DataType::Text)])))));
+}
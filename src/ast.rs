@@ -1,15 +1,64 @@
 
-use std::{sync::Arc, collections::BTreeMap, fmt::Display};
+use std::{sync::Arc, collections::BTreeMap, fmt::Display, ops::Range};
 
 use chrono::NaiveDate;
 
+use crate::{lexer::DecimalSeparator, printer::Printer};
+
+
+/// A parsed formula: its `;` separated expressions, and the comments around them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    pub expressions: Vec<Expression>,
+    /// The byte range of each expression in the formula text, so that comments can be placed relative to them.
+    pub spans: Vec<Range<usize>>,
+    /// The comments around each expression.
+    pub trivia: Vec<Trivia>,
+    /// Comments that are not around an expression or a function argument, such as a comment between the operands
+    /// of an operator. Formatters write them before the expression that they appear in.
+    pub comments: Vec<Comment>,
+}
+
+/// A comment in a formula. Comments have no effect on evaluation and are kept as trivia for tools such as formatters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub kind: CommentKind,
+    /// The text of the comment, without the `//` or `/* */` delimiters.
+    pub text: Arc<str>,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentKind {
+    Line,
+    Block,
+}
+
+/// The comments written around an expression or a function argument.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trivia {
+    /// Comments before the expression.
+    pub leading: Vec<Comment>,
+    /// Comments after the expression, including those after its separator on the same line, as in `1, // one`.
+    pub trailing: Vec<Comment>,
+}
+
+impl Trivia {
+    pub fn is_empty(&self) -> bool {
+        self.leading.is_empty() && self.trailing.is_empty()
+    }
+
+    /// Whether any of the comments is a `//` comment, which must be followed by a line break.
+    pub fn has_line_comment(&self) -> bool {
+        self.leading.iter().chain(&self.trailing).any(|c| c.kind == CommentKind::Line)
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Date(NaiveDate),
     Number(f64),
     Boolean(bool),
-    OptionSet(OptionSet),
     Text(Arc<str>),
     Image(Arc<str>),
     Hyperlink(Arc<str>),
@@ -21,10 +70,7 @@ pub enum Literal {
 
 impl Literal {
     pub fn is_blank(&self) -> bool {
-        match self {
-            Literal::Blank => true,
-            _ => false,
-        }
+        matches!(self, Literal::Blank)
     }
 
     pub fn is_not_blank(&self) -> bool {
@@ -38,7 +84,6 @@ impl Display for Literal {
             Literal::Date(d) => write!(f, "{}", d),
             Literal::Number(n) => write!(f, "{}", n),
             Literal::Boolean(b) => write!(f, "{}", b),
-            Literal::OptionSet(o) => write!(f, "{:?}", o),
             Literal::Text(t) => write!(f, "{}", t),
             Literal::Image(i) => write!(f, "{}", i),
             Literal::Hyperlink(h) => write!(f, "{}", h),
@@ -50,11 +95,6 @@ impl Display for Literal {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct OptionSet {
-    pub options: BTreeMap<i64, String>,
-}
-
 #[derive(Debug, Clone, PartialEq)]
 pub struct Record {
     pub fields: BTreeMap<Arc<str>, Expression>
@@ -81,9 +121,20 @@ pub enum Expression {
     FunctionExpression(FunctionExpression),
 }
 
+/// Writes the expression as canonical Power Fx with only the parentheses that it needs. It is written on one line,
+/// except that a call with a `//` comment on an argument has its arguments on separate lines, as the comment ends
+/// its line.
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&Printer::new(DecimalSeparator::Dot).print_expression(self))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum UnaryExpression {
     Not(Box<Expression>),
+    Negate(Box<Expression>),
+    Percent(Box<Expression>),
     IsBlank(Box<Expression>),
     IsNotBlank(Box<Expression>),
     Literal(Literal),
@@ -91,6 +142,7 @@ pub enum UnaryExpression {
     Parameter(Arc<str>),
     Identifier(Arc<str>),    
     Alias { source: Box<Expression>, alias: Arc<str> },
+    Member { source: Box<Expression>, member: Arc<str> },
 }
 
 impl UnaryExpression {
@@ -101,6 +153,10 @@ impl UnaryExpression {
     pub fn alias(source: Expression, alias: Arc<str>) -> Expression {
         Expression::UnaryExpression(Self::Alias { source: Box::new(source), alias })
     }
+
+    pub fn member(source: Expression, member: Arc<str>) -> Expression {
+        Expression::UnaryExpression(Self::Member { source: Box::new(source), member })
+    }
     
     pub fn parameter(name: Arc<str>) -> Expression {
         Expression::UnaryExpression(UnaryExpression::Parameter(name))
@@ -114,6 +170,14 @@ impl UnaryExpression {
         Expression::UnaryExpression(Self::Not(Box::new(cond)))
     }
 
+    pub fn negate(expr: Expression) -> Expression {
+        Expression::UnaryExpression(Self::Negate(Box::new(expr)))
+    }
+
+    pub fn percent(expr: Expression) -> Expression {
+        Expression::UnaryExpression(Self::Percent(Box::new(expr)))
+    }
+
     pub fn ident(ident: Arc<str>) -> Expression {
         Expression::UnaryExpression(Self::Identifier(ident))
     }
@@ -144,7 +208,7 @@ pub enum BinaryExpression {
     Subtract(Box<Expression>, Box<Expression>),
     Multiply(Box<Expression>, Box<Expression>),
     Divide(Box<Expression>, Box<Expression>),
-    Modulo(Box<Expression>, Box<Expression>),
+    Concat(Box<Expression>, Box<Expression>),
     Exponent(Box<Expression>, Box<Expression>),
 
 }
@@ -202,8 +266,8 @@ impl BinaryExpression {
         Expression::BinaryExpression(Self::Divide(Box::new(a), Box::new(b)))
     }
 
-    pub fn modulo(a: Expression, b: Expression) -> Expression {
-        Expression::BinaryExpression(Self::Modulo(Box::new(a), Box::new(b)))
+    pub fn concat(a: Expression, b: Expression) -> Expression {
+        Expression::BinaryExpression(Self::Concat(Box::new(a), Box::new(b)))
     }
 
     pub fn exponent(a: Expression, b: Expression) -> Expression {
@@ -217,10 +281,20 @@ impl BinaryExpression {
 pub struct FunctionExpression {
     pub name: Arc<str>, 
     pub args: Vec<Expression>,
+    /// The comments around each argument, or no entries if there are none.
+    pub trivia: Vec<Trivia>,
 }
 
 impl FunctionExpression {
   pub fn function(name: Arc<str>, args: Vec<Expression>) -> Expression {
-    Expression::FunctionExpression(FunctionExpression{ name, args })
+    Expression::FunctionExpression(FunctionExpression{ name, args, trivia: Vec::new() })
+  }
+
+  pub fn function_with_trivia(name: Arc<str>, args: Vec<(Expression, Trivia)>) -> Expression {
+    let (args, mut trivia): (Vec<_>, Vec<_>) = args.into_iter().unzip();
+    if trivia.iter().all(Trivia::is_empty) {
+      trivia.clear();
+    }
+    Expression::FunctionExpression(FunctionExpression{ name, args, trivia })
   }
 }
\ No newline at end of file
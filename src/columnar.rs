@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+
+use crate::{evaluator::EvaluationError, models::{DataType, DataValue, Record, Schema}};
+
+
+/// A column of values that all have the same type. `None` is a blank value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Number(Vec<Option<f64>>),
+    Text(Vec<Option<Arc<str>>>),
+    Boolean(Vec<Option<bool>>),
+    Date(Vec<Option<NaiveDate>>),
+}
+
+impl Column {
+    pub fn data_type(&self) -> DataType {
+        match self {
+            Column::Number(_) => DataType::Number,
+            Column::Text(_) => DataType::Text,
+            Column::Boolean(_) => DataType::Boolean,
+            Column::Date(_) => DataType::Date,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Number(v) => v.len(),
+            Column::Text(v) => v.len(),
+            Column::Boolean(v) => v.len(),
+            Column::Date(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> DataValue {
+        match self {
+            Column::Number(v) => v[index].map(DataValue::Number),
+            Column::Text(v) => v[index].clone().map(DataValue::Text),
+            Column::Boolean(v) => v[index].map(DataValue::Boolean),
+            Column::Date(v) => v[index].map(DataValue::Date),
+        }.unwrap_or(DataValue::Blank)
+    }
+
+    fn select(&self, indices: &[usize]) -> Column {
+        match self {
+            Column::Number(v) => Column::Number(indices.iter().map(|i| v[*i]).collect()),
+            Column::Text(v) => Column::Text(indices.iter().map(|i| v[*i].clone()).collect()),
+            Column::Boolean(v) => Column::Boolean(indices.iter().map(|i| v[*i]).collect()),
+            Column::Date(v) => Column::Date(indices.iter().map(|i| v[*i]).collect()),
+        }
+    }
+}
+
+/// A table stored as one typed vector per column, with a schema that is shared by the tables derived from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnarTable {
+    schema: Arc<Schema>,
+    columns: Vec<Column>,
+    len: usize,
+}
+
+impl ColumnarTable {
+    /// Creates a table from named columns, which must all have the same length.
+    pub fn new(columns: Vec<(Arc<str>, Column)>) -> Result<ColumnarTable, EvaluationError> {
+        let len = columns.first().map(|(_, c)| c.len()).unwrap_or(0);
+        if let Some((name, _)) = columns.iter().find(|(_, c)| c.len() != len) {
+            return Err(EvaluationError::InvalidArgument(format!("Column {} has a different length to the other columns", name)));
+        }
+
+        let schema = Schema::new(columns.iter().map(|(name, c)| (name.clone(), c.data_type())).collect());
+        if schema.fields().iter().enumerate().any(|(i, (name, _))| schema.position(name) != Some(i)) {
+            return Err(EvaluationError::InvalidArgument("Column names must be unique".to_string()));
+        }
+
+        Ok(ColumnarTable {
+            schema: Arc::new(schema),
+            columns: columns.into_iter().map(|(_, c)| c).collect(),
+            len,
+        })
+    }
+
+    pub fn schema(&self) -> &Arc<Schema> {
+        &self.schema
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.schema.position(name).map(|i| &self.columns[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Materialises the row at `index` as a record.
+    pub fn row(&self, index: usize) -> Record {
+        self.partial_row(index, 0..self.columns.len())
+    }
+
+    /// Materialises only the given columns of the row at `index`.
+    pub(crate) fn partial_row(&self, index: usize, columns: impl Iterator<Item = usize>) -> Record {
+        Record::new(columns.map(|i| (self.schema.fields()[i].0.clone(), self.columns[i].get(index))).collect())
+    }
+
+    /// Creates a table with the rows at `indices`, sharing this table's schema.
+    pub(crate) fn select(&self, indices: &[usize]) -> ColumnarTable {
+        ColumnarTable {
+            schema: self.schema.clone(),
+            columns: self.columns.iter().map(|c| c.select(indices)).collect(),
+            len: indices.len(),
+        }
+    }
+}
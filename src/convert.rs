@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+
+use crate::{evaluator::EvaluationError, models::{DataType, DataValue, Record, Schema, Table}};
+
+
+/// A Rust type that can be passed to and returned from formulas.
+pub trait PowerFxValue: Sized {
+    /// The Power Fx type that values of this type convert to.
+    fn data_type() -> DataType;
+
+    fn into_data_value(self) -> DataValue;
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError>;
+
+    /// Whether a closure argument of this type can be left out of a call, in which case it converts from Blank.
+    fn is_optional() -> bool {
+        false
+    }
+}
+
+/// A Rust type that is exposed to formulas as a record, usually by `#[derive(PowerFxRecord)]`.
+pub trait PowerFxRecord: PowerFxValue {
+    /// The names and types of the fields of the record.
+    fn schema() -> Schema;
+}
+
+fn invalid_type(expected: &str, found: &DataValue) -> EvaluationError {
+    EvaluationError::InvalidArgument(format!("Expected {}, found {:?}", expected, found))
+}
+
+macro_rules! impl_float {
+    ($($t:ty),*) => {
+        $(
+            impl PowerFxValue for $t {
+                fn data_type() -> DataType {
+                    DataType::Number
+                }
+
+                fn into_data_value(self) -> DataValue {
+                    DataValue::Number(self as f64)
+                }
+
+                fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+                    match value {
+                        DataValue::Number(n) => Ok(n as $t),
+                        v => Err(invalid_type("a number", &v)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+// Numbers only convert to an integer type if they are whole and in its range, rather than being truncated or
+// saturated. `MAX as f64` rounds up to a power of two for 64 bit types, so `MAX as f64 + 1.0` is an exact bound.
+macro_rules! impl_integer {
+    ($($t:ty),*) => {
+        $(
+            impl PowerFxValue for $t {
+                fn data_type() -> DataType {
+                    DataType::Number
+                }
+
+                fn into_data_value(self) -> DataValue {
+                    DataValue::Number(self as f64)
+                }
+
+                fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+                    match value {
+                        DataValue::Number(n) if n.fract() == 0.0 && n >= <$t>::MIN as f64 && n < <$t>::MAX as f64 + 1.0 => Ok(n as $t),
+                        DataValue::Number(n) => Err(EvaluationError::InvalidArgument(format!("Expected a whole number that fits in {}, found {}", stringify!($t), n))),
+                        v => Err(invalid_type("a number", &v)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_float!(f64, f32);
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl PowerFxValue for bool {
+    fn data_type() -> DataType {
+        DataType::Boolean
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Boolean(self)
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Boolean(b) => Ok(b),
+            v => Err(invalid_type("a boolean", &v)),
+        }
+    }
+}
+
+impl PowerFxValue for String {
+    fn data_type() -> DataType {
+        DataType::Text
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Text(Arc::from(self))
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Text(t) => Ok(t.to_string()),
+            v => Err(invalid_type("text", &v)),
+        }
+    }
+}
+
+impl PowerFxValue for Arc<str> {
+    fn data_type() -> DataType {
+        DataType::Text
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Text(self)
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Text(t) => Ok(t),
+            v => Err(invalid_type("text", &v)),
+        }
+    }
+}
+
+impl PowerFxValue for NaiveDate {
+    fn data_type() -> DataType {
+        DataType::Date
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Date(self)
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Date(d) => Ok(d),
+            v => Err(invalid_type("a date", &v)),
+        }
+    }
+}
+
+/// `None` is Blank.
+impl<T: PowerFxValue> PowerFxValue for Option<T> {
+    fn data_type() -> DataType {
+        T::data_type()
+    }
+
+    fn into_data_value(self) -> DataValue {
+        match self {
+            Some(v) => v.into_data_value(),
+            None => DataValue::Blank,
+        }
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Blank => Ok(None),
+            v => T::from_data_value(v).map(Some),
+        }
+    }
+
+    fn is_optional() -> bool {
+        true
+    }
+}
+
+/// A vector of records is a table. A vector of any other type is a single column table with a `Value` column.
+impl<T: PowerFxValue> PowerFxValue for Vec<T> {
+    fn data_type() -> DataType {
+        match T::data_type() {
+            DataType::Record(schema) => DataType::Table(schema),
+            t => DataType::Table(Arc::new(Schema::new(vec![(Arc::from("Value"), t)]))),
+        }
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Table(self.into_iter().map(|v| match v.into_data_value() {
+            DataValue::Record(r) => r,
+            v => Record::from(vec![(Arc::from("Value"), v)]),
+        }).collect())
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        let table = match value {
+            DataValue::Table(t) => t,
+            v => return Err(invalid_type("a table", &v)),
+        };
+
+        let is_record = matches!(T::data_type(), DataType::Record(_));
+        table.iter().map(|row| {
+            if is_record {
+                T::from_data_value(DataValue::Record(row))
+            } else {
+                T::from_data_value(row.get("Value").cloned().unwrap_or(DataValue::Blank))
+            }
+        }).collect()
+    }
+}
+
+impl PowerFxValue for Record {
+    fn data_type() -> DataType {
+        DataType::Record(Arc::new(Schema::new(Vec::new())))
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Record(self)
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Record(r) => Ok(r),
+            v => Err(invalid_type("a record", &v)),
+        }
+    }
+}
+
+impl PowerFxValue for Table {
+    fn data_type() -> DataType {
+        DataType::Table(Arc::new(Schema::new(Vec::new())))
+    }
+
+    fn into_data_value(self) -> DataValue {
+        DataValue::Table(self)
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        match value {
+            DataValue::Table(t) => Ok(t),
+            v => Err(invalid_type("a table", &v)),
+        }
+    }
+}
+
+/// Any value, passed through unchanged. Formulas see its type as untyped.
+impl PowerFxValue for DataValue {
+    fn data_type() -> DataType {
+        DataType::Untyped
+    }
+
+    fn into_data_value(self) -> DataValue {
+        self
+    }
+
+    fn from_data_value(value: DataValue) -> Result<Self, EvaluationError> {
+        Ok(value)
+    }
+}
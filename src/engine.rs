@@ -1,10 +1,26 @@
-use std::process::Output;
 use std::sync::Arc;
 
-use crate::evaluator::{ExpressionEvaluator, EvaluationError, Session, GlobalVariables};
-use crate::function_registry::{FunctionRegistry, ScalarFunction, Function};
+use crate::evaluator::{join_result, ExpressionEvaluator, EvaluationError, Session};
+use crate::ast::Expression;
+use crate::function_registry::{AsyncScalarFunction, FunctionKind, FunctionMetadata, FunctionRegistry, ScalarFunction, Function};
+use crate::native_function::IntoScalarFunction;
+use crate::validator::{FormulaMode, Validator};
+use crate::visitor::RenameOptions;
 use crate::{functions, lexer, models};
-use crate::models::DataValue;
+use crate::lexer::DecimalSeparator;
+use crate::models::{DataValue, EnumType, OptionSetType};
+
+use tokio::runtime::Handle;
+
+
+/// Options that control how a formula is checked and evaluated.
+#[derive(Debug, Clone, Default)]
+pub struct EvaluationOptions {
+    /// Whether the formula is a data formula or a behavior formula.
+    pub mode: FormulaMode,
+    /// Whether the formula writes decimals with a dot or a comma, which also decides its list and chaining separators.
+    pub decimal_separator: DecimalSeparator,
+}
 
 
 /// The PowerFxEngine is the main entry point for the PowerFx engine. It is responsible for evaluating expressions and managing the function registry.
@@ -13,6 +29,12 @@ pub struct PowerFxEngine {
     evaluator: Arc<ExpressionEvaluator>,
 }
 
+impl Default for PowerFxEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PowerFxEngine {
     pub fn new() -> PowerFxEngine {
         let function_registry = Arc::new(FunctionRegistry::new());
@@ -23,24 +45,41 @@ impl PowerFxEngine {
         function_registry.register_function("First", Function::Scalar(Arc::new(functions::table::First::new(evaluator.clone()))));
         function_registry.register_function("Last", Function::Scalar(Arc::new(functions::table::Last::new(evaluator.clone()))));
         function_registry.register_function("Index", Function::Scalar(Arc::new(functions::table::Index::new(evaluator.clone()))));
+        function_registry.register_function("ForAll", Function::Scalar(Arc::new(functions::table::ForAll::new(evaluator.clone()))));
         function_registry.register_function("Filter", Function::Scalar(Arc::new(functions::table::Filter::new(evaluator.clone()))));
         
         function_registry.register_function("Set", Function::Scalar(Arc::new(functions::context::Set::new(evaluator.clone()))));
-        //function_registry.register_function("Set", Function::Scalar(Arc::new(functions::context::::new(evaluator.clone()))));
+        function_registry.register_function("UpdateContext", Function::Scalar(Arc::new(functions::context::UpdateContext::new(evaluator.clone()))));
+        function_registry.register_function("With", Function::Scalar(Arc::new(functions::context::With::new(evaluator.clone()))));
 
         function_registry.register_function("If", Function::Scalar(Arc::new(functions::logical::If::new(evaluator.clone()))));
+        function_registry.register_function("Switch", Function::Scalar(Arc::new(functions::logical::Switch::new(evaluator.clone()))));
         function_registry.register_function("And", Function::Scalar(Arc::new(functions::logical::And::new(evaluator.clone()))));
         function_registry.register_function("Or", Function::Scalar(Arc::new(functions::logical::Or::new(evaluator.clone()))));
         function_registry.register_function("Not", Function::Scalar(Arc::new(functions::logical::Not::new(evaluator.clone()))));
+        function_registry.register_function("Boolean", Function::Scalar(Arc::new(functions::logical::Boolean::new(evaluator.clone()))));
+        function_registry.register_function("Coalesce", Function::Scalar(Arc::new(functions::logical::Coalesce::new(evaluator.clone()))));
+        function_registry.register_function("IsBlank", Function::Scalar(Arc::new(functions::logical::IsBlank::new(evaluator.clone()))));
+        function_registry.register_function("IsEmpty", Function::Scalar(Arc::new(functions::logical::IsEmpty::new(evaluator.clone()))));
+        function_registry.register_function("Blank", Function::Scalar(Arc::new(functions::logical::Blank::new())));
 
         function_registry.register_function("Abs", Function::Scalar(Arc::new(functions::math::Abs::new(evaluator.clone()))));
         function_registry.register_function("Sqrt", Function::Scalar(Arc::new(functions::math::Sqrt::new(evaluator.clone()))));
+        function_registry.register_function("Mod", Function::Scalar(Arc::new(functions::math::Mod::new(evaluator.clone()))));
+
+        function_registry.register_function("Date", Function::Scalar(Arc::new(functions::date::Date::new(evaluator.clone()))));
 
         function_registry.register_function("Left", Function::Scalar(Arc::new(functions::text::Left::new(evaluator.clone()))));
         function_registry.register_function("Mid", Function::Scalar(Arc::new(functions::text::Mid::new(evaluator.clone()))));
         function_registry.register_function("Right", Function::Scalar(Arc::new(functions::text::Right::new(evaluator.clone()))));
         function_registry.register_function("Upper", Function::Scalar(Arc::new(functions::text::Upper::new(evaluator.clone()))));
         function_registry.register_function("Lower", Function::Scalar(Arc::new(functions::text::Lower::new(evaluator.clone()))));
+        function_registry.register_function("Text", Function::Scalar(Arc::new(functions::text::Text::new(evaluator.clone()))));
+        function_registry.register_function("Value", Function::Scalar(Arc::new(functions::text::Value::new(evaluator.clone()))));
+
+        function_registry.register_function("ParseJSON", Function::Scalar(Arc::new(functions::json::ParseJSON::new(evaluator.clone()))));
+        function_registry.register_function("JSON", Function::Scalar(Arc::new(functions::json::Json::new(evaluator.clone()))));
+        function_registry.register_enum(functions::json::json_format());
 
         function_registry.register_function("Average", Function::Scalar(Arc::new(functions::aggregation::Average::new(evaluator.clone()))));
         function_registry.register_function("Sum", Function::Scalar(Arc::new(functions::aggregation::Sum::new(evaluator.clone()))));
@@ -54,31 +93,176 @@ impl PowerFxEngine {
     }
 
     /// Registers a scalar function with the engine. The function will be available for evaluation in expressions.
-    pub fn register_scalar_function(&self, name: &str, factory: fn(Arc<ExpressionEvaluator>) -> Arc<dyn ScalarFunction>) {
+    pub fn register_scalar_function(&self, name: &str, factory: impl FnOnce(Arc<ExpressionEvaluator>) -> Arc<dyn ScalarFunction>) {
         let function = factory(self.evaluator.clone());
         self.function_registry.register_function(name, Function::Scalar(function));
     }
 
+    /// Registers an async function, which can be called by formulas evaluated with `evaluate_async`.
+    pub fn register_async_function(&self, name: &str, function: Arc<dyn AsyncScalarFunction>) {
+        self.function_registry.register_function(name, Function::Async(function));
+    }
+
+    /// Registers a Rust closure or function as a formula function. Its arguments are evaluated and converted with
+    /// `PowerFxValue`, so `|price: f64, pct: f64| price * (1.0 - pct)` can be called as `Discount(price, pct)`.
+    /// The closure can capture state, such as a connection pool, and can return a `Result` to report errors.
+    ///
+    /// The function is registered as pure. Closures with side effects must be registered with `register_fn_with`.
+    pub fn register_fn<Args>(&self, name: &str, function: impl IntoScalarFunction<Args>) {
+        self.register_fn_with(name, "", FunctionKind::Pure, function);
+    }
+
+    /// Registers a Rust closure as `register_fn` does, with a description for `list_functions` and its kind.
+    /// Closures with side effects, such as writing to a database, are registered as `FunctionKind::Behavior`, so
+    /// they are rejected in data formulas and are never run concurrently by `ForAll`.
+    pub fn register_fn_with<Args>(&self, name: &str, description: &str, kind: FunctionKind, function: impl IntoScalarFunction<Args>) {
+        let function = function.into_scalar_function(name, description, kind, self.evaluator.clone());
+        self.function_registry.register_function(name, Function::Scalar(function));
+    }
+
+    /// Registers an enum, so formulas can refer to its values as `Name.Value`. Each value stands for another
+    /// value, such as a color code, which functions can read from `EnumValue::value`.
+    pub fn register_enum(&self, name: &str, values: Vec<(&str, DataValue)>) {
+        self.function_registry.register_enum(EnumType::new(name, values));
+    }
+
+    /// Registers an option set, so formulas can refer to its options as `Name.Option`. Each option is given as its
+    /// name, the number stored for it, and the display name that `Text` returns.
+    pub fn register_option_set(&self, name: &str, options: Vec<(&str, i64, &str)>) {
+        self.function_registry.register_option_set(OptionSetType::new(name, options));
+    }
+
+    /// Creates an engine layered over this one, such as for one tenant. Functions registered with the overlay,
+    /// or hidden from it, do not affect this engine, while functions registered with this engine later are
+    /// visible through the overlay unless it overrides or hides them.
+    pub fn overlay(&self) -> PowerFxEngine {
+        let function_registry = Arc::new(FunctionRegistry::layered(self.function_registry.clone()));
+        let evaluator = Arc::new(ExpressionEvaluator::new(function_registry.clone()));
+
+        PowerFxEngine {
+            function_registry,
+            evaluator,
+        }
+    }
+
+    /// Removes a function registered with this engine, returning whether there was one.
+    pub fn unregister_function(&self, name: &str) -> bool {
+        self.function_registry.unregister_function(name)
+    }
+
+    /// Hides a function that this engine inherits from the engine it is an overlay of, such as `Set`.
+    pub fn hide_function(&self, name: &str) {
+        self.function_registry.hide_function(name);
+    }
+
+    /// The metadata of every function available to formulas, in name order.
+    pub fn list_functions(&self) -> Vec<Arc<FunctionMetadata>> {
+        self.function_registry.list()
+    }
+
+    /// Requires function names in formulas to match the casing they were registered with. By default, `sum(...)`
+    /// calls `Sum`.
+    pub fn set_strict_names(&self, strict: bool) {
+        self.function_registry.set_strict(strict);
+    }
+
+    /// Options for `rename_in_formula` that match how this engine resolves names: whether names are strict, and
+    /// which enums and option sets are registered.
+    pub fn rename_options(&self) -> RenameOptions {
+        RenameOptions {
+            strict_names: self.function_registry.is_strict(),
+            type_names: self.function_registry.type_names(),
+        }
+    }
+
     /// Evaluates the provided expression and returns the result.
     pub fn evaluate(&self, expression: &str, session: Option<&mut Session>) -> Result<DataValue, EvaluationError> {
-        let expressions = match lexer::parse(expression) {
-            Ok(e) => e,
-            Err(e) => return Err(EvaluationError::ParseError(e.to_string())),
-        };
+        self.evaluate_with_options(expression, session, &EvaluationOptions::default())
+    }
+
+    /// Checks the provided expression against the given options without evaluating it.
+    pub fn validate(&self, expression: &str, options: &EvaluationOptions) -> Result<(), EvaluationError> {
+        self.parse(expression, options).map(|_| ())
+    }
+
+    /// Evaluates the provided expression using the given options and returns the result.
+    /// The expression is validated before any part of it is evaluated.
+    pub fn evaluate_with_options(&self, expression: &str, session: Option<&mut Session>, options: &EvaluationOptions) -> Result<DataValue, EvaluationError> {
+        let expressions = self.parse(expression, options)?;
         
         let ctx = match session {
             Some(c) => c,
             None => &mut Session::new(),
         };
                 
-        let mut result = models::DataValue::Blank;
-        for expression in expressions {
-            result = match self.evaluator.evaluate_expression(ctx, &expression) {
-                Ok(r) => r,
-                Err(e) => return Err(e),
-            };
+        evaluate_all(&self.evaluator, &self.function_registry, ctx, &expressions)
+    }
+
+    /// Evaluates the provided expression, awaiting any async functions it calls, and returns the result.
+    ///
+    /// This must be called from within a tokio runtime, or else it returns `AsyncRuntimeRequired`. The formula is
+    /// evaluated on a blocking thread, so awaiting host functions does not block the runtime's workers. The formula
+    /// awaits one async call at a time, except that the rows of `ForAll` are evaluated concurrently when they call
+    /// async functions.
+    ///
+    /// The formula is evaluated with a copy of the session, which replaces the session once evaluation finishes.
+    /// If the returned future is dropped first, the session is left as it was. The formula still runs to completion
+    /// on its thread, so host functions it calls may still be called, but its changes to variables are discarded.
+    /// If the runtime cancels the evaluation, e.g. as it shuts down, the result is `Cancelled` and the session is also
+    /// left as it was.
+    pub async fn evaluate_async(&self, expression: &str, session: Option<&mut Session>) -> Result<DataValue, EvaluationError> {
+        self.evaluate_async_with_options(expression, session, &EvaluationOptions::default()).await
+    }
+
+    /// Evaluates the provided expression using the given options, awaiting any async functions it calls.
+    pub async fn evaluate_async_with_options(&self, expression: &str, session: Option<&mut Session>, options: &EvaluationOptions) -> Result<DataValue, EvaluationError> {
+        let expressions = self.parse(expression, options)?;
+
+        let runtime = Handle::try_current().map_err(|_| EvaluationError::AsyncRuntimeRequired)?;
+
+        let mut ctx = session.as_deref().cloned().unwrap_or_default();
+        ctx.set_runtime(Some(runtime));
+
+        let evaluator = self.evaluator.clone();
+        let function_registry = self.function_registry.clone();
+        let joined = tokio::task::spawn_blocking(move || {
+            let result = evaluate_all(&evaluator, &function_registry, &mut ctx, &expressions);
+            (ctx, result)
+        }).await;
+        let (mut ctx, result) = join_result(joined)?;
+
+        ctx.set_runtime(None);
+        if let Some(session) = session {
+            *session = ctx;
         }
 
-        Ok(result)        
+        result
     }
-}
\ No newline at end of file
+
+    fn parse(&self, expression: &str, options: &EvaluationOptions) -> Result<Vec<Expression>, EvaluationError> {
+        let expressions = match lexer::parse(expression, options.decimal_separator) {
+            Ok(e) => e,
+            Err(e) => return Err(EvaluationError::ParseError(e.to_string())),
+        };
+
+        Validator::new(&self.function_registry, options.mode).validate(&expressions)?;
+
+        Ok(expressions)
+    }
+}
+
+/// Evaluates each expression in turn, resolving function names in `function_registry`, and returns the last result.
+fn evaluate_all(evaluator: &ExpressionEvaluator, function_registry: &Arc<FunctionRegistry>, ctx: &mut Session, expressions: &[Expression]) -> Result<DataValue, EvaluationError> {
+    let previous = ctx.replace_function_registry(Some(function_registry.clone()));
+
+    let mut result = Ok(models::DataValue::Blank);
+    for expression in expressions {
+        result = evaluator.evaluate_expression(ctx, expression);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    ctx.replace_function_registry(previous);
+    result
+}
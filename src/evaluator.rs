@@ -1,6 +1,7 @@
-use std::{collections::BTreeMap, sync::Arc, ops::Deref};
+use std::{collections::BTreeMap, fmt::{self, Display, Formatter}, sync::Arc};
 
-use time::Date;
+use chrono::Duration;
+use tokio::{runtime::Handle, task::JoinError};
 
 use crate::{ast, ast::Literal, function_registry::{FunctionRegistry, Function}, models::{DataValue, self}};
 
@@ -10,41 +11,196 @@ use crate::{ast, ast::Literal, function_registry::{FunctionRegistry, Function},
 pub enum EvaluationError {
     DivideByZero,
     InvalidType,
-    UnknownIdentifier(String),
-    UnknownFunction(String),
+    /// A name that is not in scope. `did_you_mean` is the closest name that is, if one is close enough to be a likely typo.
+    UnknownIdentifier { name: String, did_you_mean: Option<String> },
+    /// A function that is not registered. `did_you_mean` is the closest registered function, if one is close enough.
+    UnknownFunction { name: String, did_you_mean: Option<String> },
     InvalidArgument(String),
     InvalidArgumentCount(String),
     ParseError(String),
+    BehaviorFunctionNotAllowed(String),
+    /// An async function was called by `evaluate` rather than `evaluate_async`.
+    AsyncEvaluationRequired(String),
+    /// `evaluate_async` was called outside a tokio runtime.
+    AsyncRuntimeRequired,
+    /// The runtime cancelled a task that was evaluating the formula, e.g. because it was shutting down.
+    Cancelled,
+    ChainingNotAllowed,
 }
 
 
-pub type GlobalVariables = BTreeMap<Arc<str>, DataValue>;
+impl Display for EvaluationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EvaluationError::DivideByZero => write!(f, "Division by zero"),
+            EvaluationError::InvalidType => write!(f, "Invalid type"),
+            EvaluationError::UnknownIdentifier { name, did_you_mean } => {
+                write!(f, "Unknown name {}", name)?;
+                did_you_mean.iter().try_for_each(|suggestion| write!(f, ", did you mean {}?", suggestion))
+            },
+            EvaluationError::UnknownFunction { name, did_you_mean } => {
+                write!(f, "Unknown function {}", name)?;
+                did_you_mean.iter().try_for_each(|suggestion| write!(f, ", did you mean {}?", suggestion))
+            },
+            EvaluationError::InvalidArgument(message) => write!(f, "{}", message),
+            EvaluationError::InvalidArgumentCount(message) => write!(f, "{}", message),
+            EvaluationError::ParseError(message) => write!(f, "Parse error: {}", message),
+            EvaluationError::BehaviorFunctionNotAllowed(name) => write!(f, "{} has side effects and is not allowed in a data formula", name),
+            EvaluationError::AsyncEvaluationRequired(name) => write!(f, "{} is async and can only be called by evaluate_async", name),
+            EvaluationError::AsyncRuntimeRequired => write!(f, "evaluate_async must be called within a tokio runtime"),
+            EvaluationError::Cancelled => write!(f, "The evaluation was cancelled"),
+            EvaluationError::ChainingNotAllowed => write!(f, "Chained formulas are not allowed in a data formula"),
+        }
+    }
+}
+
+impl std::error::Error for EvaluationError {}
+
+impl EvaluationError {
+    pub(crate) fn unknown_identifier<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> EvaluationError {
+        EvaluationError::UnknownIdentifier {
+            name: name.to_string(),
+            did_you_mean: did_you_mean(name, candidates),
+        }
+    }
+
+    pub(crate) fn unknown_function<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> EvaluationError {
+        EvaluationError::UnknownFunction {
+            name: name.to_string(),
+            did_you_mean: did_you_mean(name, candidates),
+        }
+    }
+}
 
-pub trait MyToString {
-    fn to_string(&self) -> String;
+/// Finds the candidate closest to `name`, ignoring case. Candidates more than a third of the length of `name`
+/// away by edit distance are not considered, and ties go to the candidate that comes first alphabetically.
+fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let lowercase_name: Vec<char> = name.to_lowercase().chars().collect();
+    let max_distance = (lowercase_name.len() / 3).max(1);
+
+    candidates.into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(&lowercase_name, &candidate.to_lowercase().chars().collect::<Vec<_>>()), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
 }
 
-impl MyToString for GlobalVariables {
-    fn to_string(&self) -> String {
-        let mut result = String::new();
-        for (key, value) in self {
-            result.push_str(&format!("{}={}\n", key, value));
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
         }
-        result
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Whether two values are equal as compared by `=`. Blank can be compared with any value, but comparing values of
+/// two different types is an error.
+pub(crate) fn values_equal(v1: &DataValue, v2: &DataValue) -> Result<bool, EvaluationError> {
+    let equal = match (v1, v2) {
+        (DataValue::Number(n1), DataValue::Number(n2)) => n1 == n2,
+        (DataValue::Date(n1), DataValue::Date(n2)) => n1 == n2,
+        (DataValue::Text(s1), DataValue::Text(s2)) => s1 == s2,
+        (DataValue::Hyperlink(s1), DataValue::Hyperlink(s2)) => s1 == s2,
+        (DataValue::Image(s1), DataValue::Image(s2)) => s1 == s2,
+        (DataValue::Media(s1), DataValue::Media(s2)) => s1 == s2,
+        (DataValue::Boolean(b1), DataValue::Boolean(b2)) => b1 == b2,
+        (DataValue::Record(r1), DataValue::Record(r2)) => r1 == r2,
+        (DataValue::Table(t1), DataValue::Table(t2)) => t1 == t2,
+        (DataValue::Enum(e1), DataValue::Enum(e2)) => e1 == e2,
+        (DataValue::OptionSet(o1), DataValue::OptionSet(o2)) => o1 == o2,
+        (DataValue::Blank, DataValue::Blank) => true,
+        (DataValue::Blank, _) | (_, DataValue::Blank) => false,
+        (v1, v2) => return Err(EvaluationError::InvalidArgument(format!("Cannot compare {:?} with {:?}", v1, v2))),
+    };
+    Ok(equal)
+}
+
+/// The result of a task that evaluated part of a formula. A panic in the task is resumed on this thread, and a task
+/// that the runtime cancelled is a `Cancelled` error.
+pub(crate) fn join_result<T>(result: Result<T, JoinError>) -> Result<T, EvaluationError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(EvaluationError::Cancelled),
+    }
+}
+
+/// Coerces an operand of an arithmetic or ordering operator to a number. Text is parsed, booleans are 1 or 0, and
+/// Blank and empty text are 0.
+pub(crate) fn coerce_to_number(value: DataValue) -> Result<f64, EvaluationError> {
+    match value {
+        DataValue::Number(n) => Ok(n),
+        DataValue::Boolean(b) => Ok(if b { 1.0 } else { 0.0 }),
+        DataValue::Text(t) if t.trim().is_empty() => Ok(0.0),
+        DataValue::Text(t) => t.trim().parse::<f64>()
+            .map_err(|_| EvaluationError::InvalidArgument(format!("Cannot convert \"{}\" to a number", t))),
+        DataValue::Blank => Ok(0.0),
+        v => Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", v))),
+    }
+}
+
+/// Coerces an operand of `&` to text. Blank is empty text.
+fn coerce_to_text(value: DataValue) -> Result<String, EvaluationError> {
+    match value {
+        DataValue::Text(t) | DataValue::Hyperlink(t) => Ok(t.to_string()),
+        DataValue::Number(n) => Ok(n.to_string()),
+        DataValue::Boolean(b) => Ok(b.to_string()),
+        DataValue::Date(d) => Ok(d.format("%Y-%m-%d").to_string()),
+        DataValue::OptionSet(o) => Ok(o.display_name().to_string()),
+        DataValue::Enum(e) => match e.value.as_ref() {
+            DataValue::Text(t) => Ok(t.to_string()),
+            DataValue::Number(n) => Ok(n.to_string()),
+            _ => Err(EvaluationError::InvalidArgument(format!("{}.{} does not stand for text", e.enum_name, e.name))),
+        },
+        DataValue::Blank => Ok(String::new()),
+        v => Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", v))),
     }
 }
 
+pub type GlobalVariables = BTreeMap<Arc<str>, DataValue>;
+
+/// The variables visible to a formula, arranged in scopes.
+///
+/// Names are resolved from the innermost scope outwards: the scopes opened by `With` and by functions that
+/// evaluate a formula per row (such as `Filter`), then context variables set by `UpdateContext`, and finally
+/// global variables set by `Set`. A name in an inner scope shadows the same name in an outer one without
+/// changing it.
+///
+/// Sessions are cheap to clone: the variables are shared with the clone until one of them sets a variable.
 #[derive(Debug, Clone)]
 pub struct Session {
-  variables: GlobalVariables,
-  
+  variables: Arc<GlobalVariables>,
+  context_variables: Arc<GlobalVariables>,
+  scopes: Vec<Arc<GlobalVariables>>,
+  /// The runtime that async functions are run on, set while the session is used by `evaluate_async`.
+  runtime: Option<Handle>,
+  /// The functions of the engine evaluating a formula with the session, which may be an overlay of the engine
+  /// that created the built-in functions.
+  functions: Option<Arc<FunctionRegistry>>,
+}
+
+impl Default for Session {
+  fn default() -> Self {
+    Self::new()
+  }
 }
 
 impl Session {
 
   pub fn new() -> Session {
     Session {
-        variables: GlobalVariables::new(),
+        variables: Arc::new(GlobalVariables::new()),
+        context_variables: Arc::new(GlobalVariables::new()),
+        scopes: Vec::new(),
+        runtime: None,
+        functions: None,
     }
   }
 
@@ -54,34 +210,113 @@ impl Session {
         variables.insert(key.clone(), value.clone());
     }
     Session {
-        variables,
+        variables: Arc::new(variables),
+        context_variables: Arc::new(GlobalVariables::new()),
+        scopes: Vec::new(),
+        runtime: None,
+        functions: None,
     }
   }
 
+  /// Creates a copy of `context` with the fields of `record` in a new innermost scope.
+  ///
+  /// The copy shares the variables of `context`, but still allocates; when evaluating a formula per row,
+  /// `with_record` avoids the copy altogether.
   pub fn from_record_with_context(record: &models::Record, context: &Session) -> Session {
-    let mut variables = context.clone_variables();
-    for (key, value) in record.fields.iter() {
-        variables.insert(key.clone(), value.clone());
-    }
-    Session {
-        variables,
-    }
+    let mut session = context.clone();
+    session.scopes.push(record.fields.clone());
+    session
   }
 
   pub fn replace_variables(&mut self, new_data: GlobalVariables) {
-    self.variables = new_data;
+    self.variables = Arc::new(new_data);
   }
 
+  /// Resolves a name through the scope chain, returning the innermost value.
   pub fn get_variable(&self, name: &str) -> Option<&DataValue> {
+    self.get_variable_outside(name, 0)
+  }
+
+  /// Resolves a name through the scope chain, skipping the `skip` innermost scopes.
+  fn get_variable_outside(&self, name: &str, skip: usize) -> Option<&DataValue> {
+    for scope in self.scopes.iter().rev().skip(skip) {
+      if let Some(value) = scope.get(name) {
+        return Some(value);
+      }
+    }
+
+    if let Some(value) = self.context_variables.get(name) {
+      return Some(value);
+    }
+
     self.variables.get(name)    
   }
 
+  /// The record that `ThisRecord` refers to: the innermost scope opened by `With` or by a function that evaluates a
+  /// formula per row.
+  pub(crate) fn this_record(&self) -> Option<&GlobalVariables> {
+    self.scopes.last().map(|scope| scope.as_ref())
+  }
+
+  /// Resolves a name as `Parent.name` does, in the scopes around the innermost one. Returns `None` for the outer
+  /// result if there is no innermost scope to look around.
+  pub(crate) fn get_parent_variable(&self, name: &str) -> Option<Option<&DataValue>> {
+    if self.scopes.is_empty() {
+      return None;
+    }
+    Some(self.get_variable_outside(name, 1))
+  }
+
+  /// Sets a global variable.
   pub fn set_variable(&mut self, name: &str, value: DataValue) {
-    self.variables.insert(Arc::from(name), value);
+    Arc::make_mut(&mut self.variables).insert(Arc::from(name), value);
+  }
+
+  pub fn get_context_variable(&self, name: &str) -> Option<&DataValue> {
+    self.context_variables.get(name)
+  }
+
+  /// Sets a context variable, which shadows a global variable of the same name.
+  pub fn set_context_variable(&mut self, name: &str, value: DataValue) {
+    Arc::make_mut(&mut self.context_variables).insert(Arc::from(name), value);
+  }
+
+  pub(crate) fn runtime(&self) -> Option<&Handle> {
+    self.runtime.as_ref()
+  }
+
+  pub(crate) fn set_runtime(&mut self, runtime: Option<Handle>) {
+    self.runtime = runtime;
+  }
+
+  /// Sets the registry that function names are resolved in, returning the previous one.
+  pub(crate) fn replace_function_registry(&mut self, functions: Option<Arc<FunctionRegistry>>) -> Option<Arc<FunctionRegistry>> {
+    std::mem::replace(&mut self.functions, functions)
+  }
+
+  /// The names of every variable in scope, including shadowed ones.
+  pub fn variable_names(&self) -> impl Iterator<Item = &str> {
+    self.scopes.iter().rev().flat_map(|scope| scope.keys())
+      .chain(self.context_variables.keys())
+      .chain(self.variables.keys())
+      .map(|name| name.as_ref())
   }
 
   pub fn clone_variables(&self) -> GlobalVariables {
-    self.variables.clone()
+    self.variables.as_ref().clone()
+  }
+
+  /// Runs `f` with `scope` pushed as the innermost scope, and removes it again afterwards.
+  pub fn with_scope<T>(&mut self, scope: Arc<GlobalVariables>, f: impl FnOnce(&mut Session) -> T) -> T {
+    self.scopes.push(scope);
+    let result = f(self);
+    self.scopes.pop();
+    result
+  }
+
+  /// Runs `f` with the fields of `record` as the innermost scope. The fields are shared with the record rather than copied.
+  pub fn with_record<T>(&mut self, record: &models::Record, f: impl FnOnce(&mut Session) -> T) -> T {
+    self.with_scope(record.fields.clone(), f)
   }
   
 }
@@ -98,6 +333,12 @@ impl ExpressionEvaluator {
         }
     }
 
+    /// The registry that function names are resolved in: the session's, if it is being evaluated by an engine,
+    /// or else the one the evaluator was created with.
+    pub(crate) fn function_registry<'a>(&'a self, context: &'a Session) -> &'a FunctionRegistry {
+        context.functions.as_deref().unwrap_or(&self.function_registry)
+    }
+
     pub fn evaluate_expression(
         &self,
         context: &mut Session,
@@ -117,6 +358,8 @@ impl ExpressionEvaluator {
         }
     }
 
+    /// Evaluates a condition, as the logical operators and functions do. Blank counts as false, and any value other
+    /// than a boolean is an error.
     pub fn evaluate_predicate(
         &self,
         context: &mut Session,
@@ -125,7 +368,8 @@ impl ExpressionEvaluator {
         let value = self.evaluate_expression(context, expression)?;
         match value {
             DataValue::Boolean(b) => Ok(b),
-            _ => Ok(false),
+            DataValue::Blank => Ok(false),
+            _ => Err(EvaluationError::InvalidArgument(format!("Expected a boolean, found {:?}", expression))),
         }
     }
 
@@ -159,6 +403,8 @@ impl ExpressionEvaluator {
             ast::UnaryExpression::Not(expression) => {
                 DataValue::Boolean(!self.evaluate_predicate(context, expression)?)
             }
+            ast::UnaryExpression::Negate(e) => DataValue::Number(-coerce_to_number(self.evaluate_expression(context, e)?)?),
+            ast::UnaryExpression::Percent(e) => DataValue::Number(coerce_to_number(self.evaluate_expression(context, e)?)? / 100.0),
             ast::UnaryExpression::IsBlank(e) => DataValue::Boolean(self.evaluate_expression(context, e)?.is_blank()),
             ast::UnaryExpression::IsNotBlank(e) => DataValue::Boolean(!self.evaluate_expression(context, e)?.is_blank()),
             ast::UnaryExpression::Literal(l) => {
@@ -176,7 +422,7 @@ impl ExpressionEvaluator {
                         for (key, value) in r.fields.iter() {
                             fields.insert(key.clone(), self.evaluate_expression(context, value)?);
                         }
-                        DataValue::Record(models::Record{fields})
+                        DataValue::Record(models::Record::new(fields))
                     },
                     Literal::Table(t) => {
                         let mut records = Vec::new();
@@ -185,35 +431,103 @@ impl ExpressionEvaluator {
                             for (key, value) in record.fields.iter() {
                                 fields.insert(key.clone(), self.evaluate_expression(context, value)?);
                             }
-                            records.push(models::Record{fields});
-                        }
-                        DataValue::Table(records)
-                    },
-                    Literal::OptionSet(o) => {
-                        let mut options = BTreeMap::new();
-                        for (key, value) in o.options.iter() {
-                            options.insert(*key, value.clone());
+                            records.push(models::Record::new(fields));
                         }
-                        DataValue::OptionSet(models::OptionSet{options})
+                        DataValue::Table(records.into())
                     },
                 }
             },
-            ast::UnaryExpression::Property { context, key } => todo!(),            
+            ast::UnaryExpression::Property { context: property_context, key } => self.evaluate_property(context, property_context, key)?,
             ast::UnaryExpression::Alias { source, alias: _ } => {
                 self.evaluate_expression(context, source)?
             }
+            ast::UnaryExpression::Member { source, member } => match self.evaluate_named_value(context, source, member)? {
+                Some(value) => value,
+                None => {
+                    let source = self.evaluate_expression(context, source)?;
+                    self.evaluate_member(source, member)?
+                },
+            },
             ast::UnaryExpression::Identifier(ident) => match context.get_variable(ident) {
                 Some(value) => value.clone(),
-                None => return Err(EvaluationError::UnknownIdentifier(ident.to_string())),
+                None => return Err(EvaluationError::unknown_identifier(ident, context.variable_names())),
             },
             ast::UnaryExpression::Parameter(name) => match context.get_variable(name) {
                 Some(value) => value.clone(),
-                None => return Err(EvaluationError::UnknownIdentifier(name.to_string())),
+                None => return Err(EvaluationError::unknown_identifier(name, context.variable_names())),
             },
         };
         Ok(result)
     }
 
+    /// Resolves `ThisRecord.key` in the innermost record scope, and `Parent.key` in the scopes around it. `Self`
+    /// refers to a control, which formulas evaluated by the engine do not have.
+    fn evaluate_property(&self, context: &Session, property_context: &ast::Context, key: &str) -> Result<DataValue, EvaluationError> {
+        let value = match property_context {
+            ast::Context::ThisRecord | ast::Context::ThisItem => match context.this_record() {
+                Some(record) => match record.get(key) {
+                    Some(value) => value,
+                    None => return Err(EvaluationError::unknown_identifier(key, record.keys().map(|k| k.as_ref()))),
+                },
+                None => return Err(EvaluationError::InvalidArgument(format!("{}.{} can only be used in a formula that is evaluated for a record", context_name(property_context), key))),
+            },
+            ast::Context::Parent => match context.get_parent_variable(key) {
+                Some(Some(value)) => value,
+                Some(None) => return Err(EvaluationError::unknown_identifier(key, context.variable_names())),
+                None => return Err(EvaluationError::InvalidArgument(format!("Parent.{} can only be used in a formula that is evaluated for a record", key))),
+            },
+            ast::Context::Self_ => return Err(EvaluationError::InvalidArgument(format!("Self.{} is not supported", key))),
+        };
+        Ok(value.clone())
+    }
+
+    /// Resolves `Name.Value` when `Name` is a registered enum or option set. A variable of the same name takes
+    /// precedence.
+    fn evaluate_named_value(&self, context: &Session, source: &ast::Expression, member: &str) -> Result<Option<DataValue>, EvaluationError> {
+        let name = match source {
+            ast::Expression::UnaryExpression(ast::UnaryExpression::Identifier(name)) if context.get_variable(name).is_none() => name,
+            _ => return Ok(None),
+        };
+
+        let registry = self.function_registry(context);
+        let (value, names): (_, Vec<String>) = if let Some(enum_type) = registry.get_enum(name) {
+            (enum_type.get(member).cloned().map(DataValue::Enum), enum_type.values().iter().map(|v| format!("{}.{}", name, v.name)).collect())
+        } else if let Some(option_set) = registry.get_option_set(name) {
+            (option_set.option(member).map(DataValue::OptionSet), option_set.members().iter().map(|m| format!("{}.{}", name, m.name)).collect())
+        } else {
+            return Ok(None);
+        };
+
+        match value {
+            Some(value) => Ok(Some(value)),
+            None => Err(EvaluationError::unknown_identifier(&format!("{}.{}", name, member), names.iter().map(|n| n.as_str()))),
+        }
+    }
+
+    /// Reads a field of a record or untyped object. For a table, this is the single column table of that field.
+    fn evaluate_member(&self, source: DataValue, member: &str) -> Result<DataValue, EvaluationError> {
+        match source {
+            DataValue::Record(r) => match r.get(member) {
+                Some(value) => Ok(value.clone()),
+                None => Err(EvaluationError::unknown_identifier(member, r.fields.keys().map(|k| k.as_ref()))),
+            },
+            DataValue::Table(t) => {
+                let mut rows = Vec::new();
+                for row in t.iter() {
+                    let value = self.evaluate_member(DataValue::Record(row), member)?;
+                    rows.push(models::Record::from(vec![(Arc::from(member), value)]));
+                }
+                Ok(DataValue::Table(rows.into()))
+            },
+            DataValue::Untyped(u) => match u.as_ref() {
+                serde_json::Value::Object(o) => Ok(o.get(member).cloned().map(DataValue::untyped).unwrap_or(DataValue::Blank)),
+                v => Err(EvaluationError::InvalidArgument(format!("Cannot read field {} of untyped value {}", member, v))),
+            },
+            DataValue::Blank => Ok(DataValue::Blank),
+            v => Err(EvaluationError::InvalidArgument(format!("Cannot read field {} of {:?}", member, v))),
+        }
+    }
+
     fn evaluate_binary_expression(
         &self,
         context: &mut Session,
@@ -226,141 +540,83 @@ impl ExpressionEvaluator {
             ast::BinaryExpression::Or(c1, c2) => DataValue::Boolean(
                 self.evaluate_predicate(context, c1)? || self.evaluate_predicate(context, c2)?,
             ),
-            ast::BinaryExpression::Eq(e1, e2) => match (
-                self.evaluate_expression(context, e1)?,
-                self.evaluate_expression(context, e2)?,
-            ) {
-                (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Boolean(n1 == n2),
-                (DataValue::Date(n1), DataValue::Date(n2)) => DataValue::Boolean(n1 == n2),
-                (DataValue::Text(s1), DataValue::Text(s2)) => DataValue::Boolean(s1 == s2),
-                (DataValue::Hyperlink(s1), DataValue::Hyperlink(s2)) => DataValue::Boolean(s1 == s2),
-                (DataValue::Image(s1), DataValue::Image(s2)) => DataValue::Boolean(s1 == s2),
-                (DataValue::Media(s1), DataValue::Media(s2)) => DataValue::Boolean(s1 == s2),
-                (DataValue::Boolean(b1), DataValue::Boolean(b2)) => DataValue::Boolean(b1 == b2),
-                (DataValue::Blank, DataValue::Blank) => DataValue::Boolean(true),
-                (DataValue::Record(r1), DataValue::Record(r2)) => DataValue::Boolean(r1 == r2),
-                (DataValue::Table(t1), DataValue::Table(t2)) => DataValue::Boolean(t1 == t2),                
-                _ => DataValue::Boolean(false),
-            },
-            ast::BinaryExpression::Ne(e1, e2) => match (
-                self.evaluate_expression(context, e1)?,
-                self.evaluate_expression(context, e2)?,
-            ) {
-                (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Boolean(n1 != n2),
-                (DataValue::Date(n1), DataValue::Date(n2)) => DataValue::Boolean(n1 != n2),
-                (DataValue::Text(s1), DataValue::Text(s2)) => DataValue::Boolean(s1 != s2),
-                (DataValue::Hyperlink(s1), DataValue::Hyperlink(s2)) => DataValue::Boolean(s1 != s2),
-                (DataValue::Image(s1), DataValue::Image(s2)) => DataValue::Boolean(s1 != s2),
-                (DataValue::Media(s1), DataValue::Media(s2)) => DataValue::Boolean(s1 != s2),
-                (DataValue::Boolean(b1), DataValue::Boolean(b2)) => DataValue::Boolean(b1 != b2),
-                (DataValue::Blank, DataValue::Blank) => DataValue::Boolean(false),
-                (DataValue::Record(r1), DataValue::Record(r2)) => DataValue::Boolean(r1 != r2),
-                (DataValue::Table(t1), DataValue::Table(t2)) => DataValue::Boolean(t1 != t2),                
-                _ => DataValue::Boolean(true),
-            },
+            ast::BinaryExpression::Eq(e1, e2) => DataValue::Boolean(values_equal(
+                &self.evaluate_expression(context, e1)?,
+                &self.evaluate_expression(context, e2)?,
+            )?),
+            ast::BinaryExpression::Ne(e1, e2) => DataValue::Boolean(!values_equal(
+                &self.evaluate_expression(context, e1)?,
+                &self.evaluate_expression(context, e2)?,
+            )?),
             ast::BinaryExpression::Lt(e1, e2) => match (
                 self.evaluate_expression(context, e1)?,
                 self.evaluate_expression(context, e2)?,
             ) {
-                (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Boolean(n1 < n2),                
                 (DataValue::Date(n1), DataValue::Date(n2)) => DataValue::Boolean(n1 < n2),
-                _ => DataValue::Boolean(false),
+                (v1, v2) => DataValue::Boolean(coerce_to_number(v1)? < coerce_to_number(v2)?),
             },
             ast::BinaryExpression::Le(e1, e2) => match (
                 self.evaluate_expression(context, e1)?,
                 self.evaluate_expression(context, e2)?,
             ) {
-                (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Boolean(n1 <= n2),
                 (DataValue::Date(n1), DataValue::Date(n2)) => DataValue::Boolean(n1 <= n2),
-                _ => DataValue::Boolean(false),
+                (v1, v2) => DataValue::Boolean(coerce_to_number(v1)? <= coerce_to_number(v2)?),
             },
             ast::BinaryExpression::Gt(e1, e2) => match (
                 self.evaluate_expression(context, e1)?,
                 self.evaluate_expression(context, e2)?,
             ) {
-                (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Boolean(n1 > n2),
                 (DataValue::Date(n1), DataValue::Date(n2)) => DataValue::Boolean(n1 > n2),
-                _ => DataValue::Boolean(false),
+                (v1, v2) => DataValue::Boolean(coerce_to_number(v1)? > coerce_to_number(v2)?),
             },
             ast::BinaryExpression::Ge(e1, e2) => match (
                 self.evaluate_expression(context, e1)?,
                 self.evaluate_expression(context, e2)?,
             ) {
-                (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Boolean(n1 >= n2),
                 (DataValue::Date(n1), DataValue::Date(n2)) => DataValue::Boolean(n1 >= n2),
-                _ => DataValue::Boolean(false),
+                (v1, v2) => DataValue::Boolean(coerce_to_number(v1)? >= coerce_to_number(v2)?),
             },
-            ast::BinaryExpression::Add(e1, e2) => {
-                let n1 = self.evaluate_expression(context, e1)?;
-                let n2 = self.evaluate_expression(context, e2)?;
-                match (n1, n2) {
-                    (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Number(n1 + n2),
-                    (DataValue::Number(n1), DataValue::Text(s2)) => DataValue::Text(Arc::from(n1.to_string() + &s2)),
-                    (DataValue::Text(s1), DataValue::Boolean(b2)) => DataValue::Text(Arc::from(s1.to_string() + &b2.to_string())),
-                    (DataValue::Text(s1), DataValue::Number(n2)) => DataValue::Text(Arc::from(s1.to_string() + &n2.to_string())),
-                    (DataValue::Text(s1), DataValue::Text(s2)) => DataValue::Text(Arc::from(s1.to_string() + &s2)),
-                    _ => DataValue::Blank,
-                }
-            }
-            ast::BinaryExpression::Subtract(e1, e2) => {
-                let n1 = self.evaluate_expression(context, e1)?;
-                let n2 = self.evaluate_expression(context, e2)?;
-                match (n1, n2) {
-                    (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Number(n1 - n2),                    
-                    _ => DataValue::Blank,
-                }
-            }
-            ast::BinaryExpression::Multiply(e1, e2) => {
-                let n1 = self.evaluate_expression(context, e1)?;
-                let n2 = self.evaluate_expression(context, e2)?;
-                match (n1, n2) {
-                    (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Number(n1 * n2),                    
-                    _ => DataValue::Blank,
-                }
-            }
+            ast::BinaryExpression::Add(e1, e2) => match (
+                self.evaluate_expression(context, e1)?,
+                self.evaluate_expression(context, e2)?,
+            ) {
+                (DataValue::Date(d), n) | (n, DataValue::Date(d)) => DataValue::Date(d + Duration::days(coerce_to_number(n)? as i64)),
+                (n1, n2) => DataValue::Number(coerce_to_number(n1)? + coerce_to_number(n2)?),
+            },
+            ast::BinaryExpression::Subtract(e1, e2) => match (
+                self.evaluate_expression(context, e1)?,
+                self.evaluate_expression(context, e2)?,
+            ) {
+                (DataValue::Date(d1), DataValue::Date(d2)) => DataValue::Number((d1 - d2).num_days() as f64),
+                (DataValue::Date(d), n) => DataValue::Date(d - Duration::days(coerce_to_number(n)? as i64)),
+                (n1, n2) => DataValue::Number(coerce_to_number(n1)? - coerce_to_number(n2)?),
+            },
+            ast::BinaryExpression::Multiply(e1, e2) => DataValue::Number(
+                coerce_to_number(self.evaluate_expression(context, e1)?)? * coerce_to_number(self.evaluate_expression(context, e2)?)?,
+            ),
             ast::BinaryExpression::Divide(e1, e2) => {
-                let n1 = self.evaluate_expression(context, e1)?;
-                let n2 = self.evaluate_expression(context, e2)?;
-                match (n1, n2) {
-                    (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Number(n1 / n2),                    
-                    _ => DataValue::Blank,
+                let n1 = coerce_to_number(self.evaluate_expression(context, e1)?)?;
+                match coerce_to_number(self.evaluate_expression(context, e2)?)? {
+                    0.0 => return Err(EvaluationError::DivideByZero),
+                    n2 => DataValue::Number(n1 / n2),
                 }
-            }
+            },
+            ast::BinaryExpression::Concat(e1, e2) => DataValue::Text(Arc::from(
+                coerce_to_text(self.evaluate_expression(context, e1)?)? + &coerce_to_text(self.evaluate_expression(context, e2)?)?,
+            )),
             ast::BinaryExpression::In(e1, e2, exact) => {
-                let e1 = self.evaluate_expression(context, e1)?;
-                let e2 = self.evaluate_expression(context, e2)?;
-                
+                let s1 = coerce_to_text(self.evaluate_expression(context, e1)?)?;
+                let s2 = coerce_to_text(self.evaluate_expression(context, e2)?)?;
+
                 if *exact {
-                    match (e1, e2) {
-                        (DataValue::Text(s1), DataValue::Text(s2)) => DataValue::Boolean(s2.contains(s1.deref())),
-                        
-                        _ => return Err(EvaluationError::InvalidType),
-                    }
+                    DataValue::Boolean(s2.contains(&s1))
                 } else {
-                    match (e1, e2) {
-                        (DataValue::Text(s1), DataValue::Text(s2)) => {
-                            let s1 = s1.to_lowercase();
-                            let s2 = s2.to_lowercase();
-                            DataValue::Boolean(s2.contains(s1.deref()))
-                        },
-                        _ => return Err(EvaluationError::InvalidType),
-                    }
-                }
-                
-            },
-            ast::BinaryExpression::Modulo(e1, e2) => {
-                let n1 = self.evaluate_expression(context, e1)?;
-                let n2 = self.evaluate_expression(context, e2)?;
-                match (n1, n2) {
-                    (DataValue::Number(n1), DataValue::Number(n2)) => DataValue::Number(n1 % n2),
-                    _ => DataValue::Blank,
+                    DataValue::Boolean(s2.to_lowercase().contains(&s1.to_lowercase()))
                 }
             },
-            ast::BinaryExpression::Exponent(e1, e2) => {
-                let n1 = self.evaluate_expression(context, e1)?;
-                let n2 = self.evaluate_expression(context, e2)?;
-                todo!()
-            },
+            ast::BinaryExpression::Exponent(e1, e2) => DataValue::Number(
+                coerce_to_number(self.evaluate_expression(context, e1)?)?.powf(coerce_to_number(self.evaluate_expression(context, e2)?)?),
+            ),
         };
         Ok(result)
     }
@@ -371,14 +627,31 @@ impl ExpressionEvaluator {
         expression: &ast::FunctionExpression,
     ) -> Result<DataValue, EvaluationError> {
         
-        let result = match self.function_registry.get_function(&expression.name) {
-            Some(function) => match function.as_ref() {
-                Function::Scalar(scalar) => scalar.call(context, &expression.args)?,
+        let resolved = self.function_registry(context).resolve(&expression.name);
+        let result = match resolved {
+            Some((function, metadata)) => {
+                metadata.signature.check_arity(&metadata.name, expression.args.len())?;
+                match function.as_ref() {
+                    Function::Scalar(scalar) => scalar.call(context, &expression.args)?,
+                    Function::Async(function) => {
+                        let runtime = match context.runtime() {
+                            Some(runtime) => runtime.clone(),
+                            None => return Err(EvaluationError::AsyncEvaluationRequired(metadata.name.clone())),
+                        };
+                        let mut args = Vec::with_capacity(expression.args.len());
+                        for arg in &expression.args {
+                            args.push(self.evaluate_expression(context, arg)?);
+                        }
+                        // `evaluate_async` runs the evaluator on a blocking thread, so this does not block the runtime's
+                        // workers. It does block the formula, though, which awaits one call at a time; only `ForAll`
+                        // evaluates rows on several threads, so that their calls are awaited concurrently.
+                        runtime.block_on(function.call(args))?
+                    },
+                }
             },
             None => {
-                return Err(EvaluationError::UnknownFunction(
-                    expression.name.to_string(),
-                ))
+                let names = self.function_registry(context).names();
+                return Err(EvaluationError::unknown_function(&expression.name, names.iter().map(|n| n.as_str())))
             }
         };
 
@@ -386,3 +659,12 @@ impl ExpressionEvaluator {
     }
 
 }
+
+fn context_name(context: &ast::Context) -> &'static str {
+    match context {
+        ast::Context::Parent => "Parent",
+        ast::Context::Self_ => "Self",
+        ast::Context::ThisItem => "ThisItem",
+        ast::Context::ThisRecord => "ThisRecord",
+    }
+}
@@ -1,40 +1,387 @@
-use std::{collections::HashMap, sync::{Arc, RwLock}};
+use std::{collections::{BTreeSet, HashMap, HashSet}, fmt::Debug, future::Future, pin::Pin, sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock}};
 
-use crate::{evaluator::{Session, EvaluationError}, ast::Expression, models::DataValue};
+use crate::{evaluator::{Session, EvaluationError}, ast::Expression, models::{DataValue, EnumType, OptionSetType}};
 
 
 pub enum Function {
   Scalar(Arc<dyn ScalarFunction>),
+  /// A function that awaits I/O, such as a database query. It can only be called by `PowerFxEngine::evaluate_async`.
+  Async(Arc<dyn AsyncScalarFunction>),
+}
+
+impl Function {
+  pub fn signature(&self) -> Signature {
+    match self {
+      Function::Scalar(f) => f.signature(),
+      Function::Async(f) => f.signature(),
+    }
+  }
+}
+
+/// Classifies a function by whether calling it can change state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+  /// Computes a value from its arguments only, and may be used in any formula.
+  Pure,
+  /// Has side effects, such as `Set`, and may only be used in behavior formulas.
+  Behavior,
+}
+
+/// The type of a parameter or return value in a signature. Records and tables are not described by their fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+  Any,
+  Number,
+  Text,
+  Boolean,
+  Date,
+  Record,
+  Table,
+  Untyped,
+  Enum,
+  OptionSet,
+  /// An argument the function evaluates itself rather than receiving its value, such as a predicate evaluated
+  /// per row or the name of a variable.
+  Formula,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+  pub name: String,
+  pub value_type: ValueType,
+}
+
+/// Describes how a function is called. The default signature accepts any number of arguments of any type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+  pub description: String,
+  pub parameters: Vec<Parameter>,
+  pub min_arity: usize,
+  /// The most arguments the function takes, or `None` if the last parameter can be repeated any number of times.
+  pub max_arity: Option<usize>,
+  pub return_type: ValueType,
+  pub kind: FunctionKind,
+}
+
+impl Default for Signature {
+  fn default() -> Self {
+    Signature {
+      description: String::new(),
+      parameters: Vec::new(),
+      min_arity: 0,
+      max_arity: None,
+      return_type: ValueType::Any,
+      kind: FunctionKind::Pure,
+    }
+  }
+}
+
+impl Signature {
+  /// Creates a signature for a pure function that takes no arguments and returns any type.
+  pub fn new(description: &str) -> Signature {
+    Signature {
+      description: description.to_string(),
+      max_arity: Some(0),
+      ..Signature::default()
+    }
+  }
+
+  /// Adds a required parameter. Required parameters must come before optional ones.
+  pub fn param(mut self, name: &str, value_type: ValueType) -> Signature {
+    self.min_arity += 1;
+    self.push(name, value_type);
+    self
+  }
+
+  pub fn optional_param(mut self, name: &str, value_type: ValueType) -> Signature {
+    self.push(name, value_type);
+    self
+  }
+
+  /// Adds a final parameter that can be repeated any number of times, including none.
+  pub fn variadic(mut self, name: &str, value_type: ValueType) -> Signature {
+    self.push(name, value_type);
+    self.max_arity = None;
+    self
+  }
+
+  pub fn returns(mut self, value_type: ValueType) -> Signature {
+    self.return_type = value_type;
+    self
+  }
+
+  /// Marks the function as having side effects.
+  pub fn behavior(mut self) -> Signature {
+    self.kind = FunctionKind::Behavior;
+    self
+  }
+
+  fn push(&mut self, name: &str, value_type: ValueType) {
+    self.parameters.push(Parameter { name: name.to_string(), value_type });
+    self.max_arity = Some(self.parameters.len());
+  }
+
+  /// Checks that `count` arguments are allowed when calling the function `name`.
+  pub fn check_arity(&self, name: &str, count: usize) -> Result<(), EvaluationError> {
+    let expected = match self.max_arity {
+      Some(max) if count >= self.min_arity && count <= max => return Ok(()),
+      None if count >= self.min_arity => return Ok(()),
+      Some(max) if max == self.min_arity => arguments(max),
+      Some(max) => format!("{} to {}", self.min_arity, arguments(max)),
+      None => format!("at least {}", arguments(self.min_arity)),
+    };
+    Err(EvaluationError::InvalidArgumentCount(format!("{} expects {}, found {}", name, expected, count)))
+  }
+}
+
+fn arguments(count: usize) -> String {
+  match count {
+    1 => "1 argument".to_string(),
+    n => format!("{} arguments", n),
+  }
+}
+
+/// A boxed future, as returned by `AsyncScalarFunction::call`.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A function whose result is awaited, such as a call to a database or another service.
+///
+/// Unlike a `ScalarFunction`, it receives its arguments already evaluated, so it cannot take formulas.
+pub trait AsyncScalarFunction: Send + Sync {
+  fn call(&self, args: Vec<DataValue>) -> BoxFuture<'_, Result<DataValue, EvaluationError>>;
+
+  fn signature(&self) -> Signature {
+    Signature::default()
+  }
+}
+
+/// A registered function's name and signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionMetadata {
+  pub name: String,
+  pub signature: Signature,
+}
+
+impl FunctionMetadata {
+  /// The namespace of a dotted name such as `Math.Round`, or `None` for a name without one.
+  pub fn namespace(&self) -> Option<&str> {
+    self.name.rsplit_once('.').map(|(namespace, _)| namespace)
+  }
 }
 
 pub trait ScalarFunction: Send + Sync {
+  #[allow(clippy::ptr_arg)]
   fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError>;
+
+  /// Describes the function's parameters, return type and kind. Calls with the wrong number of arguments are
+  /// rejected before `call` is reached.
+  fn signature(&self) -> Signature {
+    Signature::default()
+  }
 }
 
+/// The functions available to formulas, by name, and the enums and option sets they can refer to.
+///
+/// Function names are resolved without regard to case, so `sum(...)` calls `Sum`, unless the registry is strict.
+///
+/// A registry can be layered over a parent with `FunctionRegistry::layered`. Names are resolved in the layer
+/// first and then in its parent, so a layer can add and override functions, and hide its parent's functions,
+/// without changing the parent.
 pub struct FunctionRegistry {
-  functions: Arc<RwLock<HashMap<String, Arc<Function>>>>,
+  functions: Arc<RwLock<Functions>>,
+  parent: Option<Arc<FunctionRegistry>>,
+  strict: AtomicBool,
+}
+
+#[derive(Default)]
+struct Functions {
+  by_name: HashMap<String, Registration>,
+  /// Maps each lowercased name to the name the function was registered with.
+  by_lowercase_name: HashMap<String, String>,
+  /// The lowercased names of the parent's functions that this layer hides.
+  hidden: HashSet<String>,
+  enums: HashMap<String, Arc<EnumType>>,
+  option_sets: HashMap<String, Arc<OptionSetType>>,
+}
+
+struct Registration {
+  function: Arc<Function>,
+  metadata: Arc<FunctionMetadata>,
+}
+
+impl Default for FunctionRegistry {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Debug for FunctionRegistry {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("FunctionRegistry").field("functions", &self.names()).finish()
+  }
 }
 
 impl FunctionRegistry {
   pub fn new() -> FunctionRegistry {
-    let result = FunctionRegistry {
-      functions: Arc::new(RwLock::new(HashMap::new())),
-    };
-
+    FunctionRegistry {
+      functions: Arc::new(RwLock::new(Functions::default())),
+      parent: None,
+      strict: AtomicBool::new(false),
+    }
+  }
 
-    result
+  /// Creates an empty layer over `parent`, which starts with the same strictness as `parent`.
+  pub fn layered(parent: Arc<FunctionRegistry>) -> FunctionRegistry {
+    FunctionRegistry {
+      functions: Arc::new(RwLock::new(Functions::default())),
+      strict: AtomicBool::new(parent.is_strict()),
+      parent: Some(parent),
+    }
   }
 
+  /// Registers a function in this layer, replacing any function of the same name in this layer and overriding
+  /// any in its parents. Unless the registry is strict, names that differ only in case are the same name.
   pub fn register_function(&self, name: &str, function: Function) {
+    let metadata = FunctionMetadata {
+      name: name.to_string(),
+      signature: function.signature(),
+    };
     let mut lock = self.functions.write().unwrap();
-    lock.insert(name.to_string(), Arc::new(function));
+    if !self.is_strict() {
+      let lowercase_name = name.to_lowercase();
+      lock.by_name.retain(|registered, _| registered == name || registered.to_lowercase() != lowercase_name);
+    }
+    lock.by_name.insert(name.to_string(), Registration { function: Arc::new(function), metadata: Arc::new(metadata) });
+    lock.by_lowercase_name.insert(name.to_lowercase(), name.to_string());
   }
 
+  /// Removes a function registered in this layer, returning whether there was one. A function of the same name
+  /// in a parent becomes visible again.
+  pub fn unregister_function(&self, name: &str) -> bool {
+    let mut lock = self.functions.write().unwrap();
+    if lock.by_name.remove(name).is_none() {
+      return false;
+    }
+    // Point the lowercased name at another casing registered in a strict registry, if there is one.
+    let lowercase_name = name.to_lowercase();
+    if lock.by_lowercase_name.get(&lowercase_name).map(|n| n.as_str()) == Some(name) {
+      match lock.by_name.keys().find(|registered| registered.to_lowercase() == lowercase_name).cloned() {
+        Some(registered) => lock.by_lowercase_name.insert(lowercase_name, registered),
+        None => lock.by_lowercase_name.remove(&lowercase_name),
+      };
+    }
+    true
+  }
+
+  /// Hides a function of the parent registries, in any casing, from this layer and the layers over it.
+  /// Functions registered in this layer are not hidden.
+  pub fn hide_function(&self, name: &str) {
+    let mut lock = self.functions.write().unwrap();
+    lock.hidden.insert(name.to_lowercase());
+  }
+
+  /// Finds a function by its exact name or, unless the registry is strict, by a name that differs only in case.
   pub fn get_function(&self, name: &str) -> Option<Arc<Function>> {
+    self.resolve(name).map(|(function, _)| function)
+  }
+
+  pub fn get_metadata(&self, name: &str) -> Option<Arc<FunctionMetadata>> {
+    self.resolve(name).map(|(_, metadata)| metadata)
+  }
+
+  /// Finds a function together with its metadata, in the same way as `get_function`.
+  pub(crate) fn resolve(&self, name: &str) -> Option<(Arc<Function>, Arc<FunctionMetadata>)> {
+    self.resolve_in_layers(name, self.is_strict())
+  }
+
+  fn resolve_in_layers(&self, name: &str, strict: bool) -> Option<(Arc<Function>, Arc<FunctionMetadata>)> {
+    {
+      let lock = self.functions.read().unwrap();
+      let registration = match lock.by_name.get(name) {
+        Some(registration) => Some(registration),
+        None if strict => None,
+        None => lock.by_lowercase_name.get(&name.to_lowercase()).and_then(|name| lock.by_name.get(name)),
+      };
+      if let Some(registration) = registration {
+        return Some((registration.function.clone(), registration.metadata.clone()));
+      }
+      if lock.hidden.contains(&name.to_lowercase()) {
+        return None;
+      }
+    }
+
+    self.parent.as_ref()?.resolve_in_layers(name, strict)
+  }
+
+  /// The metadata of every function visible through this registry, in name order. Unless the registry is strict,
+  /// a function overridden in a layer under a different casing is listed once, as it is registered in the layer.
+  pub fn list(&self) -> Vec<Arc<FunctionMetadata>> {
+    let strict = self.is_strict();
+    let key = |name: &str| if strict { name.to_string() } else { name.to_lowercase() };
+    let mut functions = HashMap::new();
     let lock = self.functions.read().unwrap();
-    match lock.get(name) {
-      Some(f) => Some(f.clone()),
-      None => None,
+    if let Some(parent) = &self.parent {
+      for metadata in parent.list() {
+        if !lock.hidden.contains(&metadata.name.to_lowercase()) {
+          functions.insert(key(&metadata.name), metadata);
+        }
+      }
+    }
+    for (name, registration) in lock.by_name.iter() {
+      if strict || lock.by_lowercase_name.get(&name.to_lowercase()) == Some(name) {
+        functions.insert(key(name), registration.metadata.clone());
+      }
+    }
+    let mut functions: Vec<_> = functions.into_values().collect();
+    functions.sort_by(|a, b| a.name.cmp(&b.name));
+    functions
+  }
+
+  /// The names of the functions visible through this registry, in alphabetical order.
+  pub fn names(&self) -> Vec<String> {
+    self.list().iter().map(|metadata| metadata.name.clone()).collect()
+  }
+
+  /// Registers an enum in this layer, so formulas can refer to its values as `Enum.Value`.
+  pub fn register_enum(&self, enum_type: EnumType) {
+    let mut lock = self.functions.write().unwrap();
+    lock.enums.insert(enum_type.name().to_string(), Arc::new(enum_type));
+  }
+
+  pub fn get_enum(&self, name: &str) -> Option<Arc<EnumType>> {
+    if let Some(enum_type) = self.functions.read().unwrap().enums.get(name) {
+      return Some(enum_type.clone());
     }
+    self.parent.as_ref()?.get_enum(name)
+  }
+
+  /// Registers an option set in this layer, so formulas can refer to its options as `OptionSet.Option`.
+  pub fn register_option_set(&self, option_set: OptionSetType) {
+    let mut lock = self.functions.write().unwrap();
+    lock.option_sets.insert(option_set.name().to_string(), Arc::new(option_set));
+  }
+
+  pub fn get_option_set(&self, name: &str) -> Option<Arc<OptionSetType>> {
+    if let Some(option_set) = self.functions.read().unwrap().option_sets.get(name) {
+      return Some(option_set.clone());
+    }
+    self.parent.as_ref()?.get_option_set(name)
+  }
+
+  /// The names of the enums and option sets visible through this registry.
+  pub fn type_names(&self) -> BTreeSet<Arc<str>> {
+    let mut names = self.parent.as_ref().map(|parent| parent.type_names()).unwrap_or_default();
+    let lock = self.functions.read().unwrap();
+    names.extend(lock.enums.keys().chain(lock.option_sets.keys()).map(|name| Arc::from(name.as_str())));
+    names
+  }
+
+  /// Requires function names to match the registered casing exactly.
+  pub fn set_strict(&self, strict: bool) {
+    self.strict.store(strict, Ordering::Relaxed);
+  }
+
+  pub fn is_strict(&self) -> bool {
+    self.strict.load(Ordering::Relaxed)
   }
 }
@@ -1,11 +1,12 @@
 use crate::ast::{self, Expression};
-use crate::evaluator::{EvaluationError, ExpressionEvaluator};
+use crate::columnar::Column;
+use crate::evaluator::{coerce_to_number, EvaluationError, ExpressionEvaluator};
 
-use crate::models::DataValue;
+use crate::models::{DataValue, Table};
 
 use crate::evaluator::Session;
 
-use crate::function_registry::ScalarFunction;
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
 
 use std::sync::Arc;
 
@@ -24,10 +25,6 @@ impl Average {
 impl ScalarFunction for Average {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() == 0 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 1 arguments, found {}", args.len())));
-        }
-
         let mut sum = 0.0;
         let mut count = 0;
 
@@ -35,31 +32,32 @@ impl ScalarFunction for Average {
             if args.len() != 2 {
                 return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
             }
-            let expr = &args[1];
-            for row in &table {
-                let mut session = Session::from_record_with_context(row, &context);
-                let value = match self.evaluator.evaluate_expression(&mut session, expr)? {
-                    DataValue::Number(n) => n,
-                    _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", expr))),
-                };
+            for_each_number(&self.evaluator, context, &table, &args[1], |value| {
                 sum += value;
                 count += 1;
-            }
+            })?;
 
-            return Ok(DataValue::Number(sum / count as f64));            
+            return Ok(average(sum, count));
         }
 
         for arg in args {
-            let value = match self.evaluator.evaluate_expression(context, arg)? {
-                DataValue::Number(n) => n,
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", arg))),
+            let value = match number_or_blank(self.evaluator.evaluate_expression(context, arg)?)? {
+                Some(n) => n,
+                None => continue,
             };
 
             sum += value;
             count += 1;
         }
 
-        Ok(DataValue::Number(sum / count as f64))
+        Ok(average(sum, count))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the average of numbers, or of a formula over the records of a table.")
+            .param("source", ValueType::Any)
+            .variadic("value", ValueType::Any)
+            .returns(ValueType::Number)
     }
 }
 
@@ -79,33 +77,21 @@ impl Sum {
 impl ScalarFunction for Sum {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() == 0 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 1 arguments, found {}", args.len())));
-        }
-
         let mut sum = 0.0;
 
         if let DataValue::Table(table) = self.evaluator.evaluate_expression(context, &args[0])? {
             if args.len() != 2 {
                 return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
             }
-            let expr = &args[1];
-            for row in &table {
-                let mut session = Session::from_record_with_context(row, &context);
-                let value = match self.evaluator.evaluate_expression(&mut session, expr)? {
-                    DataValue::Number(n) => n,
-                    _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", expr))),
-                };
-                sum += value;
-            }
+            for_each_number(&self.evaluator, context, &table, &args[1], |value| sum += value)?;
 
             return Ok(DataValue::Number(sum));            
         }
 
         for arg in args {
-            let value = match self.evaluator.evaluate_expression(context, arg)? {
-                DataValue::Number(n) => n,
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", arg))),
+            let value = match number_or_blank(self.evaluator.evaluate_expression(context, arg)?)? {
+                Some(n) => n,
+                None => continue,
             };
 
             sum += value;
@@ -113,6 +99,13 @@ impl ScalarFunction for Sum {
 
         Ok(DataValue::Number(sum))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the sum of numbers, or of a formula over the records of a table.")
+            .param("source", ValueType::Any)
+            .variadic("value", ValueType::Any)
+            .returns(ValueType::Number)
+    }
 }
 
 
@@ -131,24 +124,13 @@ impl Min {
 impl ScalarFunction for Min {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() == 0 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 1 arguments, found {}", args.len())));
-        }
-
         let mut result = None;
 
         if let DataValue::Table(table) = self.evaluator.evaluate_expression(context, &args[0])? {
             if args.len() != 2 {
                 return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
             }
-            let expr = &args[1];
-            for row in &table {
-                let mut session = Session::from_record_with_context(row, &context);
-                let value = match self.evaluator.evaluate_expression(&mut session, expr)? {
-                    DataValue::Number(n) => n,
-                    _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", expr))),
-                };
-
+            for_each_number(&self.evaluator, context, &table, &args[1], |value| {
                 result = match result {
                     Some(r) => {
                         if value < r {
@@ -159,7 +141,7 @@ impl ScalarFunction for Min {
                     },
                     None => Some(value),
                 };
-            }
+            })?;
 
             return Ok(match result {
                 Some(r) => DataValue::Number(r),
@@ -168,9 +150,9 @@ impl ScalarFunction for Min {
         }
 
         for arg in args {
-            let value = match self.evaluator.evaluate_expression(context, arg)? {
-                DataValue::Number(n) => n,
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", arg))),
+            let value = match number_or_blank(self.evaluator.evaluate_expression(context, arg)?)? {
+                Some(n) => n,
+                None => continue,
             };
 
             result = match result {
@@ -190,6 +172,13 @@ impl ScalarFunction for Min {
             None => DataValue::Blank,
         })
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the smallest of numbers, or of a formula over the records of a table.")
+            .param("source", ValueType::Any)
+            .variadic("value", ValueType::Any)
+            .returns(ValueType::Number)
+    }
 }
 
 pub struct Max {
@@ -207,23 +196,13 @@ impl Max {
 impl ScalarFunction for Max {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() == 0 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 1 arguments, found {}", args.len())));
-        }
-
         let mut result = None;
 
         if let DataValue::Table(table) = self.evaluator.evaluate_expression(context, &args[0])? {
             if args.len() != 2 {
                 return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
             }
-            let expr = &args[1];
-            for row in &table {
-                let mut session = Session::from_record_with_context(row, &context);
-                let value = match self.evaluator.evaluate_expression(&mut session, expr)? {
-                    DataValue::Number(n) => n,
-                    _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", expr))),
-                };
+            for_each_number(&self.evaluator, context, &table, &args[1], |value| {
                 result = match result {
                     Some(r) => {
                         if value > r {
@@ -234,7 +213,7 @@ impl ScalarFunction for Max {
                     },
                     None => Some(value),
                 };
-            }
+            })?;
 
             return Ok(match result {
                 Some(r) => DataValue::Number(r),
@@ -243,9 +222,9 @@ impl ScalarFunction for Max {
         }
 
         for arg in args {
-            let value = match self.evaluator.evaluate_expression(context, arg)? {
-                DataValue::Number(n) => n,
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", arg))),
+            let value = match number_or_blank(self.evaluator.evaluate_expression(context, arg)?)? {
+                Some(n) => n,
+                None => continue,
             };
 
             result = match result {
@@ -265,4 +244,47 @@ impl ScalarFunction for Max {
             None => DataValue::Blank,
         })
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the largest of numbers, or of a formula over the records of a table.")
+            .param("source", ValueType::Any)
+            .variadic("value", ValueType::Any)
+            .returns(ValueType::Number)
+    }
+}
+
+/// The average of `count` numbers adding up to `sum`, or Blank if there were none.
+fn average(sum: f64, count: usize) -> DataValue {
+    match count {
+        0 => DataValue::Blank,
+        count => DataValue::Number(sum / count as f64),
+    }
+}
+
+/// Reads a value as a number to aggregate, converting it as arithmetic operators do. Blank values are skipped, as in
+/// Power Fx, and give `None`.
+fn number_or_blank(value: DataValue) -> Result<Option<f64>, EvaluationError> {
+    match value {
+        DataValue::Blank => Ok(None),
+        value => coerce_to_number(value).map(Some),
+    }
+}
+
+/// Evaluates `expr` for each row of `table` and passes the numbers it produces to `f`, skipping blank values. When
+/// `expr` is the name of a number column of a columnar table, the column is read directly instead.
+fn for_each_number(evaluator: &ExpressionEvaluator, context: &mut Session, table: &Table, expr: &Expression, mut f: impl FnMut(f64)) -> Result<(), EvaluationError> {
+    if let (Some(columns), Expression::UnaryExpression(ast::UnaryExpression::Identifier(name))) = (table.as_columnar(), expr) {
+        if let Some(Column::Number(values)) = columns.column(name) {
+            values.iter().flatten().for_each(|n| f(*n));
+            return Ok(());
+        }
+    }
+
+    for row in table.scopes_for(std::slice::from_ref(expr)) {
+        let value = context.with_record(&row, |row_context| evaluator.evaluate_expression(row_context, expr))?;
+        if let Some(n) = number_or_blank(value)? {
+            f(n);
+        }
+    }
+    Ok(())
 }
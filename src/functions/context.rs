@@ -5,7 +5,7 @@ use crate::models::DataValue;
 
 use crate::evaluator::Session;
 
-use crate::function_registry::ScalarFunction;
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
 
 use std::sync::Arc;
 
@@ -24,15 +24,8 @@ impl Set {
 impl ScalarFunction for Set {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
-        }
-
         let var_name = match &args[0] {
-            Expression::UnaryExpression(u) => match &*u {
-                ast::UnaryExpression::Identifier(name) => name.clone(),
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected an identifier, found {:?}", args[0]))),
-            },
+            Expression::UnaryExpression(ast::UnaryExpression::Identifier(name)) => name.clone(),
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected an identifier, found {:?}", args[0]))),
         };
 
@@ -43,4 +36,75 @@ impl ScalarFunction for Set {
 
         Ok(DataValue::Blank)
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Sets a global variable.")
+            .param("variable", ValueType::Formula)
+            .param("value", ValueType::Any)
+            .behavior()
+    }
+}
+
+pub struct UpdateContext {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl UpdateContext {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for UpdateContext {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let record = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Record(r) => r,
+            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a record, found {:?}", args[0]))),
+        };
+
+        for (key, value) in record.fields.iter() {
+            context.set_context_variable(key, value.clone());
+        }
+
+        Ok(DataValue::Blank)
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Sets a context variable for each field of a record.")
+            .param("record", ValueType::Record)
+            .behavior()
+    }
+}
+
+pub struct With {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl With {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for With {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let record = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Record(r) => r,
+            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a record, found {:?}", args[0]))),
+        };
+
+        context.with_scope(record.fields, |scope| self.evaluator.evaluate_expression(scope, &args[1]))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Evaluates a formula with the fields of a record in scope.")
+            .param("record", ValueType::Record)
+            .param("formula", ValueType::Formula)
+    }
 }
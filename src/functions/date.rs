@@ -0,0 +1,53 @@
+use crate::ast::Expression;
+use crate::evaluator::{coerce_to_number, EvaluationError, ExpressionEvaluator};
+
+use crate::models::DataValue;
+
+use crate::evaluator::Session;
+
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
+
+use chrono::NaiveDate;
+
+use std::sync::Arc;
+
+pub struct Date {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Date {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for Date {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let mut parts = [0.0; 3];
+        for (part, arg) in parts.iter_mut().zip(args) {
+            *part = coerce_to_number(self.evaluator.evaluate_expression(context, arg)?)?;
+        }
+
+        let [year, month, day] = parts;
+        let date = match parts.iter().all(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64) {
+            true => NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32),
+            false => None,
+        };
+
+        match date {
+            Some(date) => Ok(DataValue::Date(date)),
+            None => Err(EvaluationError::InvalidArgument(format!("{}-{}-{} is not a valid date", year, month, day))),
+        }
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the date for a year, month and day.")
+            .param("year", ValueType::Number)
+            .param("month", ValueType::Number)
+            .param("day", ValueType::Number)
+            .returns(ValueType::Date)
+    }
+}
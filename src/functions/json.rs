@@ -0,0 +1,198 @@
+use crate::ast::Expression;
+use crate::evaluator::{EvaluationError, ExpressionEvaluator};
+
+use crate::models::{DataValue, EnumType, Record, MAX_EXACT_INTEGER};
+
+use crate::evaluator::Session;
+
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
+
+use std::sync::Arc;
+
+pub struct ParseJSON {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl ParseJSON {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for ParseJSON {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let text = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Text(t) => t,
+            DataValue::Blank => return Ok(DataValue::Blank),
+            _ => return Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", args[0]))),
+        };
+
+        match serde_json::from_str(&text) {
+            Ok(value) => Ok(DataValue::untyped(value)),
+            Err(e) => Err(EvaluationError::InvalidArgument(format!("Invalid JSON: {}", e))),
+        }
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Parses JSON text into an untyped value.")
+            .param("text", ValueType::Text)
+            .returns(ValueType::Untyped)
+    }
+}
+
+/// The `JSONFormat` enum. Each value stands for a flag character, and flags are combined by joining them.
+pub(crate) fn json_format() -> EnumType {
+    EnumType::new("JSONFormat", vec![
+        ("Compact", DataValue::Text("".into())),
+        ("IndentFour", DataValue::Text("4".into())),
+        ("IgnoreBinaryData", DataValue::Text("G".into())),
+        ("IncludeBinaryData", DataValue::Text("B".into())),
+        ("IgnoreUnsupportedTypes", DataValue::Text("I".into())),
+        ("FlattenValueTables", DataValue::Text("_".into())),
+    ])
+}
+
+#[derive(Default)]
+struct JSONOptions {
+    indent_four: bool,
+    ignore_binary_data: bool,
+    include_binary_data: bool,
+    flatten_value_tables: bool,
+}
+
+impl JSONOptions {
+    fn parse(flags: &str) -> Result<JSONOptions, EvaluationError> {
+        let mut options = JSONOptions::default();
+        for flag in flags.chars() {
+            match flag {
+                '4' => options.indent_four = true,
+                'G' => options.ignore_binary_data = true,
+                'B' => options.include_binary_data = true,
+                // Every value type can be written, so there is nothing to ignore.
+                'I' => {},
+                '_' => options.flatten_value_tables = true,
+                _ => return Err(EvaluationError::InvalidArgument(format!("Unknown JSONFormat flag {}", flag))),
+            }
+        }
+
+        if options.ignore_binary_data && options.include_binary_data {
+            return Err(EvaluationError::InvalidArgument("IgnoreBinaryData and IncludeBinaryData cannot be combined".to_string()));
+        }
+
+        Ok(options)
+    }
+}
+
+/// Serializes a value to JSON text. Record fields are written in name order, so the output is deterministic.
+pub struct Json {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Json {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for Json {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let value = self.evaluator.evaluate_expression(context, &args[0])?;
+
+        let options = match args.get(1) {
+            Some(arg) => match self.evaluator.evaluate_expression(context, arg)? {
+                DataValue::Text(t) => JSONOptions::parse(&t)?,
+                DataValue::Enum(e) if e.enum_name.as_ref() == "JSONFormat" => match e.value.as_ref() {
+                    DataValue::Text(t) => JSONOptions::parse(t)?,
+                    v => return Err(EvaluationError::InvalidArgument(format!("Expected a JSONFormat, found {:?}", v))),
+                },
+                DataValue::Blank => JSONOptions::default(),
+                v => return Err(EvaluationError::InvalidArgument(format!("Expected a JSONFormat, found {:?}", v))),
+            },
+            None => JSONOptions::default(),
+        };
+
+        let json = to_json(&value, &options)?.unwrap_or(serde_json::Value::Null);
+
+        let text = if options.indent_four {
+            let mut buffer = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+            let mut serializer = serde_json::Serializer::with_formatter(&mut buffer, formatter);
+            serde::Serialize::serialize(&json, &mut serializer).map_err(|e| EvaluationError::InvalidArgument(e.to_string()))?;
+            String::from_utf8(buffer).map_err(|e| EvaluationError::InvalidArgument(e.to_string()))?
+        } else {
+            json.to_string()
+        };
+
+        Ok(DataValue::Text(text.into()))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Writes a value as JSON text.")
+            .param("value", ValueType::Any)
+            .optional_param("format", ValueType::Text)
+            .returns(ValueType::Text)
+    }
+}
+
+/// Converts a value to JSON. Returns `None` for binary data that is being ignored.
+fn to_json(value: &DataValue, options: &JSONOptions) -> Result<Option<serde_json::Value>, EvaluationError> {
+    let json = match value {
+        DataValue::Blank => serde_json::Value::Null,
+        DataValue::Boolean(b) => serde_json::Value::Bool(*b),
+        DataValue::Number(n) => {
+            if !n.is_finite() {
+                return Err(EvaluationError::InvalidArgument(format!("Cannot write {} as JSON", n)));
+            }
+            if n.fract() == 0.0 && n.abs() <= MAX_EXACT_INTEGER {
+                serde_json::Value::from(*n as i64)
+            } else {
+                serde_json::Value::from(*n)
+            }
+        },
+        DataValue::Text(t) => serde_json::Value::from(t.as_ref()),
+        DataValue::Hyperlink(h) => serde_json::Value::from(h.as_ref()),
+        DataValue::Date(d) => serde_json::Value::from(d.format("%Y-%m-%d").to_string()),
+        DataValue::Image(b) | DataValue::Media(b) => {
+            if options.ignore_binary_data {
+                return Ok(None);
+            }
+            if !options.include_binary_data {
+                return Err(EvaluationError::InvalidArgument("Cannot write binary data as JSON without IgnoreBinaryData or IncludeBinaryData".to_string()));
+            }
+            serde_json::Value::from(b.as_ref())
+        },
+        DataValue::OptionSet(o) => serde_json::Value::from(o.name()),
+        DataValue::Record(r) => serde_json::Value::Object(record_to_json(r, options)?),
+        DataValue::Table(t) => {
+            let mut rows = Vec::with_capacity(t.len());
+            for row in t.iter() {
+                let single_value = options.flatten_value_tables && row.fields.len() == 1;
+                match row.get("Value") {
+                    Some(value) if single_value => rows.push(to_json(value, options)?.unwrap_or(serde_json::Value::Null)),
+                    _ => rows.push(serde_json::Value::Object(record_to_json(&row, options)?)),
+                }
+            }
+            serde_json::Value::Array(rows)
+        },
+        DataValue::Untyped(u) => u.as_ref().clone(),
+        DataValue::Enum(e) => return to_json(&e.value, options),
+    };
+
+    Ok(Some(json))
+}
+
+fn record_to_json(record: &Record, options: &JSONOptions) -> Result<serde_json::Map<String, serde_json::Value>, EvaluationError> {
+    let mut fields = serde_json::Map::new();
+    for (key, value) in record.fields.iter() {
+        if let Some(json) = to_json(value, options)? {
+            fields.insert(key.to_string(), json);
+        }
+    }
+    Ok(fields)
+}
@@ -1,11 +1,11 @@
-use crate::ast::{self, Expression};
-use crate::evaluator::{EvaluationError, ExpressionEvaluator};
+use crate::ast::Expression;
+use crate::evaluator::{values_equal, EvaluationError, ExpressionEvaluator};
 
 use crate::models::DataValue;
 
 use crate::evaluator::Session;
 
-use crate::function_registry::ScalarFunction;
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
 
 use std::sync::Arc;
 
@@ -24,25 +24,68 @@ impl If {
 impl ScalarFunction for If {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() < 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 2 arguments, found {}", args.len())));
+        for pair in args.chunks(2) {
+            match pair {
+                [condition, result] => {
+                    if evaluate_condition(&self.evaluator, context, condition)? {
+                        return self.evaluator.evaluate_expression(context, result);
+                    }
+                },
+                [default] => return self.evaluator.evaluate_expression(context, default),
+                _ => unreachable!(),
+            }
         }
 
-        let condition = match self.evaluator.evaluate_expression(context, &args[0])? {
-            DataValue::Boolean(b) => b,
-            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a boolean, found {:?}", args[0]))),
-        };
+        Ok(DataValue::Blank)
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the result of the first condition that is true, or the default if none is.")
+            .param("condition", ValueType::Boolean)
+            .param("then", ValueType::Any)
+            .variadic("condition, then or default", ValueType::Any)
+    }
+}
 
-        if condition {
-            return self.evaluator.evaluate_expression(context, &args[1]);
+pub struct Switch {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Switch {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
         }
+    }
+}
 
-        if args.len() == 3 {
-            return self.evaluator.evaluate_expression(context, &args[2]);
+impl ScalarFunction for Switch {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let value = self.evaluator.evaluate_expression(context, &args[0])?;
+
+        for pair in args[1..].chunks(2) {
+            match pair {
+                [candidate, result] => {
+                    if values_equal(&value, &self.evaluator.evaluate_expression(context, candidate)?)? {
+                        return self.evaluator.evaluate_expression(context, result);
+                    }
+                },
+                [default] => return self.evaluator.evaluate_expression(context, default),
+                _ => unreachable!(),
+            }
         }
 
         Ok(DataValue::Blank)
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the result for the first match that equals a value, or the default if none does.")
+            .param("value", ValueType::Any)
+            .param("match", ValueType::Any)
+            .param("result", ValueType::Any)
+            .variadic("match, result or default", ValueType::Any)
+    }
 }
 
 pub struct And {
@@ -60,15 +103,8 @@ impl And {
 impl ScalarFunction for And {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() < 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 2 arguments, found {}", args.len())));
-        }
-
         for arg in args {
-            let value = match self.evaluator.evaluate_expression(context, arg)? {
-                DataValue::Boolean(b) => b,
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected a boolean, found {:?}", arg))),
-            };
+            let value = evaluate_condition(&self.evaluator, context, arg)?;
 
             if !value {
                 return Ok(DataValue::Boolean(false));
@@ -77,6 +113,14 @@ impl ScalarFunction for And {
 
         Ok(DataValue::Boolean(true))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns true if every argument is true.")
+            .param("logical", ValueType::Boolean)
+            .param("logical", ValueType::Boolean)
+            .variadic("logical", ValueType::Boolean)
+            .returns(ValueType::Boolean)
+    }
 }
 
 pub struct Or {
@@ -94,15 +138,8 @@ impl Or {
 impl ScalarFunction for Or {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() < 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 2 arguments, found {}", args.len())));
-        }
-
         for arg in args {
-            let value = match self.evaluator.evaluate_expression(context, arg)? {
-                DataValue::Boolean(b) => b,
-                _ => return Err(EvaluationError::InvalidArgument(format!("Expected a boolean, found {:?}", arg))),
-            };
+            let value = evaluate_condition(&self.evaluator, context, arg)?;
 
             if value {
                 return Ok(DataValue::Boolean(true));
@@ -111,6 +148,14 @@ impl ScalarFunction for Or {
 
         Ok(DataValue::Boolean(false))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns true if any argument is true.")
+            .param("logical", ValueType::Boolean)
+            .param("logical", ValueType::Boolean)
+            .variadic("logical", ValueType::Boolean)
+            .returns(ValueType::Boolean)
+    }
 }
 
 pub struct Not {
@@ -128,15 +173,178 @@ impl Not {
 impl ScalarFunction for Not {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 argument, found {}", args.len())));
+        let value = evaluate_condition(&self.evaluator, context, &args[0])?;
+
+        Ok(DataValue::Boolean(!value))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Negates a boolean.")
+            .param("logical", ValueType::Boolean)
+            .returns(ValueType::Boolean)
+    }
+}
+
+pub struct Boolean {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Boolean {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for Boolean {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let value = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Boolean(b) => b,
+            DataValue::Number(n) => n != 0.0,
+            DataValue::Text(t) => parse_boolean(&t)?,
+            DataValue::Untyped(u) => match u.as_ref() {
+                serde_json::Value::Bool(b) => *b,
+                serde_json::Value::String(s) => parse_boolean(s)?,
+                v => return Err(EvaluationError::InvalidArgument(format!("Expected an untyped boolean, found {}", v))),
+            },
+            DataValue::Blank => return Ok(DataValue::Blank),
+            v => return Err(EvaluationError::InvalidArgument(format!("Cannot convert {:?} to a boolean", v))),
+        };
+
+        Ok(DataValue::Boolean(value))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Converts text, a number or an untyped value to a boolean.")
+            .param("value", ValueType::Any)
+            .returns(ValueType::Boolean)
+    }
+}
+
+pub struct Coalesce {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Coalesce {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for Coalesce {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        for arg in args {
+            match self.evaluator.evaluate_expression(context, arg)? {
+                DataValue::Blank => continue,
+                DataValue::Text(t) if t.is_empty() => continue,
+                value => return Ok(value),
+            }
+        }
+
+        Ok(DataValue::Blank)
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the first argument that is not blank or empty text.")
+            .param("value", ValueType::Any)
+            .variadic("value", ValueType::Any)
+    }
+}
+
+pub struct IsBlank {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl IsBlank {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for IsBlank {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let value = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Blank => true,
+            DataValue::Text(t) => t.is_empty(),
+            _ => false,
+        };
+
+        Ok(DataValue::Boolean(value))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns true if a value is blank or empty text.")
+            .param("value", ValueType::Any)
+            .returns(ValueType::Boolean)
+    }
+}
+
+pub struct IsEmpty {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl IsEmpty {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
         }
+    }
+}
 
+impl ScalarFunction for IsEmpty {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
         let value = match self.evaluator.evaluate_expression(context, &args[0])? {
-            DataValue::Boolean(b) => !b,
-            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a boolean, found {:?}", args[0]))),
+            DataValue::Table(t) => t.is_empty(),
+            DataValue::Blank => true,
+            v => return Err(EvaluationError::InvalidArgument(format!("Expected a table, found {:?}", v))),
         };
 
         Ok(DataValue::Boolean(value))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns true if a table has no rows.")
+            .param("source", ValueType::Table)
+            .returns(ValueType::Boolean)
+    }
+}
+
+pub struct Blank {}
+
+impl Blank {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl ScalarFunction for Blank {
+    fn call(&self, _context: &mut Session, _args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+        Ok(DataValue::Blank)
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns a blank value.")
+    }
+}
+
+/// Evaluates a condition of a logical function or a per-row formula of `Filter`. Blank counts as false.
+pub(crate) fn evaluate_condition(evaluator: &ExpressionEvaluator, context: &mut Session, condition: &Expression) -> Result<bool, EvaluationError> {
+    evaluator.evaluate_predicate(context, condition)
+}
+
+fn parse_boolean(text: &str) -> Result<bool, EvaluationError> {
+    match text.trim().to_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(EvaluationError::InvalidArgument(format!("Cannot convert \"{}\" to a boolean", text))),
+    }
 }
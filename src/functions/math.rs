@@ -1,11 +1,11 @@
-use crate::ast::{self, Expression};
-use crate::evaluator::{EvaluationError, ExpressionEvaluator};
+use crate::ast::Expression;
+use crate::evaluator::{coerce_to_number, EvaluationError, ExpressionEvaluator};
 
 use crate::models::DataValue;
 
 use crate::evaluator::Session;
 
-use crate::function_registry::ScalarFunction;
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
 
 use std::sync::Arc;
 
@@ -24,16 +24,15 @@ impl Abs {
 impl ScalarFunction for Abs {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 arguments, found {}", args.len())));
-        }
+        let number = coerce_to_number(self.evaluator.evaluate_expression(context, &args[0])?)?;
 
-        let value = match self.evaluator.evaluate_expression(context, &args[0])? {
-            DataValue::Number(n) => n.abs(),
-            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", args[0]))),
-        };
+        Ok(DataValue::Number(number.abs()))
+    }
 
-        Ok(DataValue::Number(value))
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the absolute value of a number.")
+            .param("number", ValueType::Number)
+            .returns(ValueType::Number)
     }
 }
 
@@ -52,15 +51,47 @@ impl Sqrt {
 impl ScalarFunction for Sqrt {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 arguments, found {}", args.len())));
+        let number = coerce_to_number(self.evaluator.evaluate_expression(context, &args[0])?)?;
+
+        Ok(DataValue::Number(number.sqrt()))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the square root of a number.")
+            .param("number", ValueType::Number)
+            .returns(ValueType::Number)
+    }
+}
+
+pub struct Mod {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Mod {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
         }
+    }
+}
 
-        let value = match self.evaluator.evaluate_expression(context, &args[0])? {
-            DataValue::Number(n) => n.sqrt(),
-            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", args[0]))),
+impl ScalarFunction for Mod {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let number = coerce_to_number(self.evaluator.evaluate_expression(context, &args[0])?)?;
+        let divisor = match coerce_to_number(self.evaluator.evaluate_expression(context, &args[1])?)? {
+            0.0 => return Err(EvaluationError::DivideByZero),
+            n => n,
         };
 
-        Ok(DataValue::Number(value))
+        // The result has the sign of the divisor, as in Power Fx and Excel.
+        Ok(DataValue::Number(number - divisor * (number / divisor).floor()))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the remainder after dividing a number by a divisor.")
+            .param("number", ValueType::Number)
+            .param("divisor", ValueType::Number)
+            .returns(ValueType::Number)
     }
 }
@@ -4,4 +4,6 @@ pub mod context;
 pub mod math;
 pub mod logical;
 pub mod text;
-pub mod aggregation;
\ No newline at end of file
+pub mod aggregation;
+pub mod json;
+pub mod date;
\ No newline at end of file
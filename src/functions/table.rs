@@ -1,11 +1,13 @@
-use crate::function_registry::ScalarFunction;
-use crate::models::DataValue;
+use crate::function_registry::{Function, FunctionKind, ScalarFunction, Signature, ValueType};
+use crate::models::{DataValue, Record};
 
 use crate::ast::Expression;
+use crate::visitor::called_functions;
+use crate::functions::logical::evaluate_condition;
 
-use crate::evaluator::{EvaluationError, ExpressionEvaluator, Session};
+use crate::evaluator::{join_result, EvaluationError, ExpressionEvaluator, Session};
 
-use std::sync::Arc;
+use std::sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc};
 
 pub struct Table {
     pub(crate) evaluator: Arc<ExpressionEvaluator>,
@@ -26,20 +28,34 @@ impl ScalarFunction for Table {
             let value = self.evaluator.evaluate_expression(context, arg)?;
             match value {
                 DataValue::Table(t) => {
-                    for record in t {
+                    for record in t.iter() {
                         result.push(record);
                     }
                 },
                 DataValue::Record(r) => {
                     result.push(r);
                 },
+                DataValue::Untyped(u) => match u.as_ref() {
+                    serde_json::Value::Array(a) => {
+                        for item in a {
+                            result.push(Record::from(vec![(Arc::from("Value"), DataValue::untyped(item.clone()))]));
+                        }
+                    },
+                    v => return Err(EvaluationError::InvalidArgument(format!("Expected an untyped array, found {}", v))),
+                },
                 _ => {
                     return Err(EvaluationError::InvalidArgument(format!("Expected a table or record, found {}", value)));
                 }
             }
         }
 
-        Ok(DataValue::Table(result))
+        Ok(DataValue::Table(result.into()))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Creates a table from records, tables and untyped arrays.")
+            .variadic("record", ValueType::Any)
+            .returns(ValueType::Table)
     }
 }
 
@@ -58,20 +74,21 @@ impl First {
 impl ScalarFunction for First {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 argument, found {}", args.len())));
-        }
-
         let table = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Table(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected a table, found {:?}", args[0]))),
         };
 
-        if table.is_empty() {
-            return Ok(DataValue::Blank);
+        match table.first() {
+            Some(record) => Ok(DataValue::Record(record)),
+            None => Ok(DataValue::Blank),
         }
+    }
 
-        Ok(DataValue::Record(table[0].clone()))
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the first record of a table.")
+            .param("table", ValueType::Table)
+            .returns(ValueType::Record)
     }
 }
 
@@ -90,20 +107,21 @@ impl Last {
 impl ScalarFunction for Last {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 argument, found {}", args.len())));
-        }
-
         let table = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Table(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected a table, found {:?}", args[0]))),
         };
 
-        if table.is_empty() {
-            return Ok(DataValue::Blank);
+        match table.last() {
+            Some(record) => Ok(DataValue::Record(record)),
+            None => Ok(DataValue::Blank),
         }
+    }
 
-        Ok(DataValue::Record(table[table.len() - 1].clone()))
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the last record of a table.")
+            .param("table", ValueType::Table)
+            .returns(ValueType::Record)
     }
 }
 
@@ -122,25 +140,33 @@ impl Index {
 impl ScalarFunction for Index {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
-        }
-
         let table = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Table(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected a table, found {:?}", args[0]))),
         };
 
         let index = match self.evaluator.evaluate_expression(context, &args[1])? {
-            DataValue::Number(n) => n as usize,
+            DataValue::Number(n) => n,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected a number, found {:?}", args[1]))),
         };
 
-        if index >= table.len() {
-            return Ok(DataValue::Blank);
+        // Positions start at 1, as in Power Fx.
+        let record = match index.fract() == 0.0 && index >= 1.0 {
+            true => table.get(index as usize - 1),
+            false => None,
+        };
+
+        match record {
+            Some(record) => Ok(DataValue::Record(record)),
+            None => Err(EvaluationError::InvalidArgument(format!("Index {} is out of range for a table of {} records", index, table.len()))),
         }
+    }
 
-        Ok(DataValue::Record(table[index].clone()))            
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the record at a one-based position in a table.")
+            .param("table", ValueType::Table)
+            .param("index", ValueType::Number)
+            .returns(ValueType::Record)
     }
 }
 
@@ -159,36 +185,130 @@ impl Filter {
 impl ScalarFunction for Filter {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() < 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected at least 2 arguments, found {}", args.len())));
-        }
-
         let table = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Table(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected a table, found {:?}", args[0]))),
         };
 
-        let mut result = Vec::new();
+        let mut selected = Vec::new();
 
-        for record in table {
-            let mut and_result = true;
-            let mut session = Session::from_record_with_context(&record, &context);
-            for arg in &args[1..] {
-                let condition = match self.evaluator.evaluate_expression(&mut session, arg)? {
-                    DataValue::Boolean(b) => b,
-                    _ => return Err(EvaluationError::InvalidArgument(format!("Expected a boolean, found {:?}", arg))),
-                };
-
-                if !condition {
-                    and_result = false;
-                    break;
+        for (index, record) in table.scopes_for(&args[1..]).enumerate() {
+            let and_result = context.with_record(&record, |row_context| {
+                for arg in &args[1..] {
+                    if !evaluate_condition(&self.evaluator, row_context, arg)? {
+                        return Ok(false);
+                    }
                 }
-            }
+                Ok(true)
+            })?;
             if and_result {
-                result.push(record.clone());
+                selected.push(index);
             }
         }
 
-        Ok(DataValue::Table(result))
+        Ok(DataValue::Table(table.select(&selected)))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the records of a table that satisfy every formula.")
+            .param("table", ValueType::Table)
+            .param("formula", ValueType::Formula)
+            .variadic("formula", ValueType::Formula)
+            .returns(ValueType::Table)
+    }
+}
+
+/// The most rows of `ForAll` that are evaluated at once when its formula calls async functions. Each row being
+/// evaluated takes a thread from the runtime's blocking pool.
+const MAX_CONCURRENT_ROWS: usize = 16;
+
+pub struct ForAll {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl ForAll {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+
+    /// Rows are evaluated concurrently when the formula awaits an async function and has no side effects, so the
+    /// order the rows run in cannot be observed.
+    fn runs_concurrently(&self, context: &Session, formula: &Expression) -> bool {
+        let names = called_functions(formula);
+
+        let registry = self.evaluator.function_registry(context);
+        let functions: Vec<_> = names.iter().filter_map(|name| registry.resolve(name)).collect();
+        functions.iter().any(|(function, _)| matches!(function.as_ref(), Function::Async(_)))
+            && functions.iter().all(|(_, metadata)| metadata.signature.kind == FunctionKind::Pure)
+    }
+}
+
+impl ScalarFunction for ForAll {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let table = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Table(t) => t,
+            _ => return Err(EvaluationError::InvalidArgument(format!("Expected a table, found {:?}", args[0]))),
+        };
+
+        let values = match context.runtime() {
+            Some(runtime) if self.runs_concurrently(context, &args[1]) => {
+                let runtime = runtime.clone();
+                let rows: Arc<Vec<Record>> = Arc::new(table.scopes_for(&args[1..]).collect());
+                let formula = Arc::new(args[1].clone());
+                let next_row = Arc::new(AtomicUsize::new(0));
+                let failed = Arc::new(AtomicBool::new(false));
+
+                // Each worker takes the next row until there are none left, evaluating it in a scope of its own copy
+                // of the session. The copies share the session's variables, so no row copies them.
+                let workers: Vec<_> = (0..MAX_CONCURRENT_ROWS.min(rows.len())).map(|_| {
+                    let mut worker_context = context.clone();
+                    let evaluator = self.evaluator.clone();
+                    let (rows, formula, next_row, failed) = (rows.clone(), formula.clone(), next_row.clone(), failed.clone());
+                    runtime.spawn_blocking(move || {
+                        let mut values = Vec::new();
+                        while !failed.load(Ordering::Relaxed) {
+                            let index = next_row.fetch_add(1, Ordering::Relaxed);
+                            let Some(record) = rows.get(index) else { break };
+                            let value = worker_context.with_record(record, |row_context| evaluator.evaluate_expression(row_context, &formula));
+                            failed.fetch_or(value.is_err(), Ordering::Relaxed);
+                            values.push((index, value));
+                        }
+                        values
+                    })
+                }).collect();
+
+                let mut values = runtime.block_on(async move {
+                    let mut values = Vec::new();
+                    for worker in workers {
+                        values.extend(join_result(worker.await)?);
+                    }
+                    Ok::<_, EvaluationError>(values)
+                })?;
+                values.sort_by_key(|(index, _)| *index);
+                values.into_iter().map(|(_, value)| value).collect::<Result<Vec<_>, _>>()?
+            },
+            _ => {
+                let mut values = Vec::with_capacity(table.len());
+                for record in table.scopes_for(&args[1..]) {
+                    values.push(context.with_record(&record, |row_context| self.evaluator.evaluate_expression(row_context, &args[1]))?);
+                }
+                values
+            },
+        };
+
+        Ok(DataValue::Table(values.into_iter().map(|value| match value {
+            DataValue::Record(r) => r,
+            v => Record::from(vec![(Arc::from("Value"), v)]),
+        }).collect()))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Evaluates a formula for each record of a table, returning a table of the results.")
+            .param("table", ValueType::Table)
+            .param("formula", ValueType::Formula)
+            .returns(ValueType::Table)
     }
 }
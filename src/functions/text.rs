@@ -1,11 +1,11 @@
-use crate::ast::{self, Expression};
+use crate::ast::Expression;
 use crate::evaluator::{EvaluationError, ExpressionEvaluator};
 
 use crate::models::DataValue;
 
 use crate::evaluator::Session;
 
-use crate::function_registry::ScalarFunction;
+use crate::function_registry::{ScalarFunction, Signature, ValueType};
 
 use std::sync::Arc;
 
@@ -24,10 +24,6 @@ impl Lower {
 impl ScalarFunction for Lower {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 arguments, found {}", args.len())));
-        }
-
         let value = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Text(t) => t.to_lowercase(),
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", args[0]))),
@@ -35,6 +31,12 @@ impl ScalarFunction for Lower {
 
         Ok(DataValue::Text(value.into()))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Converts text to lower case.")
+            .param("text", ValueType::Text)
+            .returns(ValueType::Text)
+    }
 }
 
 pub struct Upper {
@@ -52,10 +54,6 @@ impl Upper {
 impl ScalarFunction for Upper {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 1 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 1 arguments, found {}", args.len())));
-        }
-
         let value = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Text(t) => t.to_uppercase(),
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", args[0]))),
@@ -63,6 +61,12 @@ impl ScalarFunction for Upper {
 
         Ok(DataValue::Text(value.into()))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Converts text to upper case.")
+            .param("text", ValueType::Text)
+            .returns(ValueType::Text)
+    }
 }
 
 
@@ -81,10 +85,6 @@ impl Left {
 impl ScalarFunction for Left {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
-        }
-
         let text = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Text(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", args[0]))),
@@ -99,6 +99,13 @@ impl ScalarFunction for Left {
 
         Ok(DataValue::Text(value.into()))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the first characters of text.")
+            .param("text", ValueType::Text)
+            .param("count", ValueType::Number)
+            .returns(ValueType::Text)
+    }
 }
 
 pub struct Right {
@@ -116,10 +123,6 @@ impl Right {
 impl ScalarFunction for Right {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() != 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments, found {}", args.len())));
-        }
-
         let text = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Text(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", args[0]))),
@@ -134,6 +137,13 @@ impl ScalarFunction for Right {
 
         Ok(DataValue::Text(value.into()))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the last characters of text.")
+            .param("text", ValueType::Text)
+            .param("count", ValueType::Number)
+            .returns(ValueType::Text)
+    }
 }
 
 pub struct Mid {
@@ -151,10 +161,6 @@ impl Mid {
 impl ScalarFunction for Mid {
     fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
     
-        if args.len() < 2 {
-            return Err(EvaluationError::InvalidArgumentCount(format!("Expected 2 arguments or more, found {}", args.len())));
-        }
-
         let text = match self.evaluator.evaluate_expression(context, &args[0])? {
             DataValue::Text(t) => t,
             _ => return Err(EvaluationError::InvalidArgument(format!("Expected text, found {:?}", args[0]))),
@@ -178,4 +184,106 @@ impl ScalarFunction for Mid {
 
         Ok(DataValue::Text(value.into()))
     }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Returns the characters of text from a one-based start position.")
+            .param("text", ValueType::Text)
+            .param("start", ValueType::Number)
+            .optional_param("count", ValueType::Number)
+            .returns(ValueType::Text)
+    }
+}
+
+pub struct Text {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Text {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for Text {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let value = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Text(t) => t.to_string(),
+            DataValue::Number(n) => n.to_string(),
+            DataValue::Boolean(b) => b.to_string(),
+            DataValue::Date(d) => d.format("%Y-%m-%d").to_string(),
+            DataValue::Hyperlink(h) => h.to_string(),
+            DataValue::OptionSet(o) => o.display_name().to_string(),
+            DataValue::Enum(e) => match e.value.as_ref() {
+                DataValue::Text(t) => t.to_string(),
+                DataValue::Number(n) => n.to_string(),
+                _ => e.name.to_string(),
+            },
+            DataValue::Untyped(u) => match u.as_ref() {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                v => return Err(EvaluationError::InvalidArgument(format!("Expected an untyped text value, found {}", v))),
+            },
+            DataValue::Blank => return Ok(DataValue::Blank),
+            v => return Err(EvaluationError::InvalidArgument(format!("Cannot convert {:?} to text", v))),
+        };
+
+        Ok(DataValue::Text(value.into()))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Converts a value to text.")
+            .param("value", ValueType::Any)
+            .returns(ValueType::Text)
+    }
+}
+
+pub struct Value {
+    pub(crate) evaluator: Arc<ExpressionEvaluator>,
+}
+
+impl Value {
+    pub fn new(evaluator: Arc<ExpressionEvaluator>) -> Self {
+        Self {
+            evaluator,
+        }
+    }
+}
+
+impl ScalarFunction for Value {
+    fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+    
+        let value = match self.evaluator.evaluate_expression(context, &args[0])? {
+            DataValue::Number(n) => n,
+            DataValue::Boolean(b) => if b { 1.0 } else { 0.0 },
+            DataValue::Text(t) => parse_number(&t)?,
+            DataValue::OptionSet(o) => o.value() as f64,
+            DataValue::Enum(e) => match e.value.as_ref() {
+                DataValue::Number(n) => *n,
+                _ => return Err(EvaluationError::InvalidArgument(format!("{}.{} does not stand for a number", e.enum_name, e.name))),
+            },
+            DataValue::Untyped(u) => match u.as_ref() {
+                serde_json::Value::Number(n) => n.as_f64().unwrap_or(f64::NAN),
+                serde_json::Value::String(s) => parse_number(s)?,
+                v => return Err(EvaluationError::InvalidArgument(format!("Expected an untyped number, found {}", v))),
+            },
+            DataValue::Blank => return Ok(DataValue::Blank),
+            v => return Err(EvaluationError::InvalidArgument(format!("Cannot convert {:?} to a number", v))),
+        };
+
+        Ok(DataValue::Number(value))
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Converts text, a boolean or an untyped value to a number.")
+            .param("value", ValueType::Any)
+            .returns(ValueType::Number)
+    }
+}
+
+fn parse_number(text: &str) -> Result<f64, EvaluationError> {
+    text.trim().parse::<f64>().map_err(|_| EvaluationError::InvalidArgument(format!("Cannot convert \"{}\" to a number", text)))
 }
@@ -1,123 +1,207 @@
 #![allow(clippy::redundant_closure_call)]
 
+use std::{cell::RefCell, collections::BTreeMap};
+
 use super::ast::*;
+use super::evaluator::EvaluationError;
+use super::visitor::{self, Visitor};
 use peg::{error::ParseError, str::LineCol};
 
 
+/// The separators a formula is written with. In locales that write decimals with a comma, as in `1,5`, arguments
+/// and record fields are separated by `;` and formulas are chained with `;;`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecimalSeparator {
+    #[default]
+    Dot,
+    Comma,
+}
+
+impl DecimalSeparator {
+    pub fn decimal_separator(self) -> &'static str {
+        match self {
+            DecimalSeparator::Dot => ".",
+            DecimalSeparator::Comma => ",",
+        }
+    }
+
+    /// Separates the arguments of a function and the fields of a record.
+    pub fn list_separator(self) -> &'static str {
+        match self {
+            DecimalSeparator::Dot => ",",
+            DecimalSeparator::Comma => ";",
+        }
+    }
+
+    /// Separates formulas that are chained to run one after another.
+    pub fn chain_separator(self) -> &'static str {
+        match self {
+            DecimalSeparator::Dot => ";",
+            DecimalSeparator::Comma => ";;",
+        }
+    }
+}
+
 peg::parser! {
-    grammar pfx() for str {
+    grammar pfx<'c>(separator: DecimalSeparator, loose: &'c RefCell<Vec<Comment>>) for str {
         use std::sync::Arc;
+        use std::ops::Range;
+        use peg::ParseLiteral;
         
         
-        rule kw_true()      = ("true")
-        rule kw_false()     = ("false")
-        rule kw_null()      = ("NULL" / "null")
-        rule kw_and()       = ("And")
-        rule kw_or()        = ("Or")
-        rule kw_not()       = ("Not")
-        rule kw_as()          = ("AS" / "as")
+        // Keywords end at a word boundary, so that names such as 'trueValue' or 'inventory' are not read as a keyword
+        // followed by a name.
+        rule kw_true()      = ("true") !alpha_num()
+        rule kw_false()     = ("false") !alpha_num()
+        rule kw_null()      = ("NULL" / "null") !alpha_num()
+        rule kw_and()       = ("And") !alpha_num()
+        rule kw_or()        = ("Or") !alpha_num()
+        rule kw_not()       = ("Not") !alpha_num()
+        rule kw_as()          = ("AS" / "as") !alpha_num()
         
-        rule kw_parent()      = ("Parent")
-        rule kw_self()        = ("Self")
-        rule kw_this_item()   = ("ThisItem")
-        rule kw_this_record() = ("ThisRecord")
+        rule kw_parent()      = ("Parent") !alpha_num()
+        rule kw_self()        = ("Self") !alpha_num()
+        rule kw_this_item()   = ("ThisItem") !alpha_num()
+        rule kw_this_record() = ("ThisRecord") !alpha_num()
         
-        rule kw_in()          = ("in")
-        rule kw_exact_in()    = ("exactin")
+        rule kw_in()          = ("in") !alpha_num()
+        rule kw_exact_in()    = ("exactin") !alpha_num()
         
 
+        rule whitespace()
+            = [' ' | '\t' | '\n' | '\r']
+
+        // e.g. '// note', '/* note */'. Every comment is also recorded in `loose`, so that those that do not end up as
+        // trivia on an argument or a chained expression are not lost.
+        rule comment() -> Comment
+            = c:(start:position!() "//" text:$([^ '\n' | '\r']*) end:position!() { Comment { kind: CommentKind::Line, text: Arc::from(text), span: start..end } }
+                / start:position!() "/*" text:$((!"*/" [_])*) "*/" end:position!() { Comment { kind: CommentKind::Block, text: Arc::from(text), span: start..end } }) {
+                loose.borrow_mut().push(c.clone());
+                c
+            }
+
+        // The comments between two tokens, e.g. before an argument.
+        rule trivia() -> Vec<Comment>
+            = c:(whitespace() { None } / c:comment() { Some(c) })* { c.into_iter().flatten().collect() }
+
+        // The comments between two tokens up to the end of the line, e.g. after the ',' in '1, // one'.
+        rule line_trivia() -> Vec<Comment>
+            = c:([' ' | '\t'] { None } / c:comment() { Some(c) })* { c.into_iter().flatten().collect() }
+
+        // Whitespace and comments, which can appear between any two tokens.
         rule _()
-            = [' ']
+            = quiet!{(whitespace() / comment())*}
 
+        // Whitespace and comments that must separate two tokens, e.g. around 'And'.
         rule __()
-            = [' ' | '\n' | '\t']
+            = quiet!{(whitespace() / comment())+}
 
         rule alpha()
-            = ['a'..='z' | 'A'..='Z']
+            = [c if c.is_alphabetic() || c == '_']
 
         rule num()
             = ['0'..='9']
 
         rule alpha_num()
-            = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
+            = [c if c.is_alphanumeric() || c == '_']
 
 
-        // e.g. '42', '-1'
-        rule integer() -> i64
-            = integer:$("-"?num()+) {? integer.parse().or(Err("invalid integer")) }
+        rule decimal_separator() = ##parse_string_literal(separator.decimal_separator())
+        rule list_separator() = _ ##parse_string_literal(separator.list_separator()) _
+        rule chain_separator() = _ ##parse_string_literal(separator.chain_separator()) _
+        rule bare_list_separator() = ##parse_string_literal(separator.list_separator())
+        rule bare_chain_separator() = ##parse_string_literal(separator.chain_separator())
 
-        // e.g. '-0.53', '34346.245', '236.0'
-        rule real() -> f64
-            = real:$("-"? num()+ "." num()+) {? real.parse().or(Err("invalid real"))}
+        // e.g. '42', '0.53', '.5', '1e6', '2.5E-3', or '0,53' with a decimal comma. A leading '-' is the unary minus operator.
+        rule number() -> f64
+            = number:$((num()+ (decimal_separator() num()+)? / decimal_separator() num()+) (['e' | 'E'] ['+' | '-']? num()+)?) {?
+                number.replace(',', ".").parse().or(Err("invalid number"))
+            }
 
         // e.g. 'TRUE', 'FALSE'
         rule boolean() -> bool
             = kw_true() { true } / kw_false() { false }
 
-        // e.g. 'hello world'
+        // e.g. "hello world", with "" for a quote inside the text
         rule text() -> Arc<str>
-            = "'" text:$([^ '\'' | '\n' | '\r']*) "'" { Arc::from(text) }
+            = "\"" text:$(([^ '"'] / "\"\"")*) "\"" { Arc::from(text.replace("\"\"", "\"")) }
 
 
         rule record() -> Record
-            = "{" __* fields:(key:ident() _* ":" _* value:expression() { (key, value) }) ** (_* "," _*) __* "}" { Record::from(fields) }
+            = "{" _ fields:(key:ident() _ ":" _ value:expression() { (key, value) }) ** list_separator() _ "}" { Record::from(fields) }
         
-        // e.g. 'TRUE', '42', 'hello world'
+        // e.g. true, 42, "hello world"
         rule literal() -> Literal
-            = i:integer() { Literal::Number(i as f64) }
-            / r:real() { Literal::Number(r) }
+            = n:number() { Literal::Number(n) }
             / b:boolean() { Literal::Boolean(b) }
             / t:text() { Literal::Text(t) }
             / r:record() { Literal::Record(r) }
 
             
         rule projection_expression() -> Expression
-            = z:expression() _* kw_as() _* a:ident() { UnaryExpression::alias(z, a) }
+            = z:expression() __ kw_as() __ a:ident() { UnaryExpression::alias(z, a) }
             / expression()
 
         
             #[cache_left_rec]
         pub rule expression() -> Expression
             = precedence!{
-                a:(@) __+ kw_and() __+ b:@ { BinaryExpression::and(a, b) }
-                a:(@) __+ kw_or() __+ b:@ { BinaryExpression::or(a, b) }
-                a:(@) __* "&&" __* b:@ { BinaryExpression::and(a, b) }
-                a:(@) __* "||" __* b:@ { BinaryExpression::or(a, b) }
+                a:(@) __ kw_and() __ b:@ { BinaryExpression::and(a, b) }
+                a:(@) __ kw_or() __ b:@ { BinaryExpression::or(a, b) }
+                a:(@) _ "&&" _ b:@ { BinaryExpression::and(a, b) }
+                a:(@) _ "||" _ b:@ { BinaryExpression::or(a, b) }
+                --
+                kw_not() __ c:(@) { UnaryExpression::not(c) }
+                "!" _ c:(@) { UnaryExpression::not(c) }
+                --
+                a:(@) _ "="  _ b:@ { BinaryExpression::eq(a, b) }
+                a:(@) _ "<>" _ b:@ { BinaryExpression::ne(a, b) }
+                a:(@) _ "<"  _ b:@ { BinaryExpression::lt(a, b) }
+                a:(@) _ "<=" _ b:@ { BinaryExpression::le(a, b) }
+                a:(@) _ ">"  _ b:@ { BinaryExpression::gt(a, b) }
+                a:(@) _ ">=" _ b:@ { BinaryExpression::ge(a, b) }
+                a:(@) _ kw_in() _ b:@ { BinaryExpression::in_(a, b, false) }
+                a:(@) _ kw_exact_in() _ b:@ { BinaryExpression::in_(a, b, true) }
+                --
+                a:(@) _ "&" !"&" _ b:@ { BinaryExpression::concat(a, b) }
+                --
+                a:(@) _ "+" _ b:@ { BinaryExpression::add(a, b) }
+                a:(@) _ "-" _ b:@ { BinaryExpression::subtract(a, b) }
                 --
-                kw_not() _+ c:(@) { UnaryExpression::not(c) }
-                "!" _* c:(@) { UnaryExpression::not(c) }
+                a:(@) _ "*" _ b:@ { BinaryExpression::multiply(a, b) }
+                a:(@) _ "/" _ b:@ { BinaryExpression::divide(a, b) }
                 --
-                a:(@) __* "="  __* b:@ { BinaryExpression::eq(a, b) }
-                a:(@) __* "<>" __* b:@ { BinaryExpression::ne(a, b) }
-                a:(@) __* "<"  __* b:@ { BinaryExpression::lt(a, b) }
-                a:(@) __* "<=" __* b:@ { BinaryExpression::le(a, b) }
-                a:(@) __* ">"  __* b:@ { BinaryExpression::gt(a, b) }
-                a:(@) __* ">=" __* b:@ { BinaryExpression::ge(a, b) }
-                a:(@) __* kw_in() __* b:@ { BinaryExpression::in_(a, b, false) }
-                a:(@) __* kw_exact_in() __* b:@ { BinaryExpression::in_(a, b, true) }
+                a:(@) _ "^" _ b:@ { BinaryExpression::exponent(a, b) }
                 --
-                a:(@) __* "+" __* b:@ { BinaryExpression::add(a, b) }
-                a:(@) __* "-" __* b:@ { BinaryExpression::subtract(a, b) }
+                "-" _ e:(@) { UnaryExpression::negate(e) }
                 --
-                a:(@) __* "*" __* b:@ { BinaryExpression::multiply(a, b) }
-                a:(@) __* "/" __* b:@ { BinaryExpression::divide(a, b) }
+                e:@ _ "%" { UnaryExpression::percent(e) }
                 --
-                a:(@) __* "%" __* b:@ { BinaryExpression::modulo(a, b) }
-                a:(@) __* "^" __* b:@ { BinaryExpression::exponent(a, b) }
+                a:(@) "." m:ident() { UnaryExpression::member(a, m) }
                 --
                 
                 l:literal() { UnaryExpression::literal(l) }
                 p:property() { UnaryExpression::property(p.0, p.1) }
-                func:ident() _* "(" __* params:expression() ** (_* "," _*) __* ")" { FunctionExpression::function(func, params ) }
+                func:function_name() _ "(" args:arguments() ")" { FunctionExpression::function_with_trivia(func, args) }
+                func:function_name() _ "(" _ ")" { FunctionExpression::function(func, Vec::new()) }
                 
                 i:ident() { UnaryExpression::ident(i) }                
                 --
                 
-                "(" __* c:expression() __* ")" { c }
+                "(" _ c:expression() _ ")" { c }
             }
 
+        // e.g. Total, Número, or in single quotes 'Order Total', with '' for a quote inside the name
         rule ident() -> Arc<str>
-            = ident:$(alpha()alpha_num()*) { Arc::from(ident) }
+            = quoted_ident()
+            / ident:$(alpha()alpha_num()*) { Arc::from(ident) }
+
+        rule quoted_ident() -> Arc<str>
+            = "'" ident:$(([^ '\'' | '\n' | '\r'] / "''")+) "'" { Arc::from(ident.replace("''", "'")) }
+
+        // e.g. 'Sum', 'Math.Round'
+        rule function_name() -> Arc<str>
+            = names:ident() ++ "." { Arc::from(names.join(".")) }
 
         rule context() -> Context
             = kw_parent() { Context::Parent }
@@ -131,12 +215,79 @@ peg::parser! {
 
 
         pub rule expressions() -> Vec<Expression>
-            = e:expression() ** (__* ";" __*) __* ";"? { e }
+            = _ e:expression() ** chain_separator() chain_separator()? _ { e }
+
+        // A function argument with the comments around it.
+        rule argument() -> (Expression, Trivia)
+            = leading:trivia() e:expression() trailing:trivia() { (e, Trivia { leading, trailing }) }
+
+        // Comments after a separator on the same line belong to the argument before it, as in '1, // one'.
+        rule arguments() -> Vec<(Expression, Trivia)>
+            = first:argument() rest:(bare_list_separator() line:line_trivia() a:argument() { (line, a) })* { with_line_trivia(first, rest) }
+
+        rule formula_item() -> ((Expression, Range<usize>), Trivia)
+            = leading:trivia() start:position!() e:expression() end:position!() trailing:trivia() { ((e, start..end), Trivia { leading, trailing }) }
+
+        pub rule formula() -> Formula
+            = first:formula_item() rest:(bare_chain_separator() line:line_trivia() i:formula_item() { (line, i) })* last:(bare_chain_separator() c:trivia() { c })? {
+                let mut items = with_line_trivia(first, rest);
+                if let Some((_, trivia)) = items.last_mut() {
+                    trivia.trailing.extend(last.into_iter().flatten());
+                }
+                let (expressions, trivia): (Vec<_>, _) = items.into_iter().unzip();
+                let (expressions, spans) = expressions.into_iter().unzip();
+                Formula { expressions, spans, trivia, comments: Vec::new() }
+            }
+            / comments:trivia() { Formula { expressions: Vec::new(), spans: Vec::new(), trivia: Vec::new(), comments } }
+
+    }
+}
 
+/// Joins items parsed as a first item and then pairs of the comments after a separator and the next item, moving
+/// those comments onto the trailing trivia of the item before the separator.
+fn with_line_trivia<T>(first: (T, Trivia), rest: Vec<(Vec<Comment>, (T, Trivia))>) -> Vec<(T, Trivia)> {
+    let mut items = vec![first];
+    for (line, item) in rest {
+        if let Some((_, trivia)) = items.last_mut() {
+            trivia.trailing.extend(line);
+        }
+        items.push(item);
     }
+    items
 }
 
-pub fn parse(input: &str) -> Result<Vec<Expression>, ParseError<LineCol>> {
-    pfx::expressions(input)
+pub fn parse(input: &str, separator: DecimalSeparator) -> Result<Vec<Expression>, ParseError<LineCol>> {
+    pfx::expressions(input, separator, &RefCell::default())
+}
+
+/// Parses a formula, keeping its comments as trivia on the arguments and expressions they were written around.
+/// Comments anywhere else, e.g. between the operands of an operator, are kept in `Formula::comments`.
+pub fn parse_formula(input: &str, separator: DecimalSeparator) -> Result<Formula, EvaluationError> {
+    let loose = RefCell::default();
+    let mut formula = pfx::formula(input, separator, &loose).map_err(|e| EvaluationError::ParseError(e.to_string()))?;
+
+    // The parser can match the same comment more than once as it backtracks, so keep one per position, and leave
+    // out those that are attached as trivia.
+    struct Attached(Vec<usize>);
+
+    impl Visitor for Attached {
+        fn visit_function_expression(&mut self, func: &FunctionExpression) {
+            self.0.extend(func.trivia.iter().flat_map(|t| t.leading.iter().chain(&t.trailing)).map(|c| c.span.start));
+            visitor::walk_function_expression(self, func);
+        }
+    }
+
+    let mut attached = Attached(formula.trivia.iter().flat_map(|t| t.leading.iter().chain(&t.trailing)).map(|c| c.span.start).collect());
+    for expression in &formula.expressions {
+        attached.visit_expression(expression);
+    }
+
+    let mut comments: BTreeMap<usize, Comment> = loose.into_inner().into_iter().map(|c| (c.span.start, c)).collect();
+    comments.extend(formula.comments.drain(..).map(|c| (c.span.start, c)));
+    for start in attached.0 {
+        comments.remove(&start);
+    }
+    formula.comments = comments.into_values().collect();
+    Ok(formula)
 }
 
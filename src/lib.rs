@@ -1,16 +1,37 @@
 pub use evaluator::{ExpressionEvaluator, EvaluationError, Session};
-pub use function_registry::{FunctionRegistry, ScalarFunction, Function};
-pub use models::DataValue;
-pub use engine::PowerFxEngine;
-pub use ast::Expression;
+pub use function_registry::{FunctionRegistry, ScalarFunction, AsyncScalarFunction, BoxFuture, Function, FunctionKind, FunctionMetadata, Parameter, Signature, ValueType};
+pub use models::{DataValue, DataType, EnumType, EnumValue, OptionSet, OptionSetMember, OptionSetType, Record, Schema, Table};
+pub use columnar::{Column, ColumnarTable};
+pub use engine::{PowerFxEngine, EvaluationOptions};
+pub use validator::FormulaMode;
+pub use ast::{BinaryExpression, Comment, CommentKind, Context, Expression, Formula, FunctionExpression, Literal, Trivia, UnaryExpression};
+pub use lexer::{parse_formula, DecimalSeparator};
+pub use printer::{convert_formula, format_formula, rename_in_formula, FormatOptions};
+pub use visitor::{Visitor, VisitorMut, RenameOptions, referenced_identifiers, called_functions, rename_identifier};
+pub use visitor::{walk_expression, walk_unary_expression, walk_binary_expression, walk_function_expression, walk_literal};
+pub use visitor::{walk_expression_mut, walk_unary_expression_mut, walk_binary_expression_mut, walk_function_expression_mut, walk_literal_mut};
+pub use convert::{PowerFxValue, PowerFxRecord};
+pub use native_function::{IntoScalarFunction, FunctionResult};
+#[cfg(feature = "derive")]
+pub use powerfx_derive::PowerFxRecord;
+#[cfg(feature = "serde")]
+pub use serialization::{to_data_value, from_data_value};
 
 mod ast;
 mod lexer;
+mod printer;
+mod visitor;
 mod function_registry;
 mod evaluator;
 mod models;
+mod columnar;
+mod convert;
+mod native_function;
 mod functions;
 mod engine;
+#[cfg(feature = "serde")]
+mod serialization;
+mod validator;
 
 #[cfg(test)]
 mod tests;
@@ -1,8 +1,14 @@
 
-use std::{sync::Arc, collections::BTreeMap, fmt::Display};
+use std::{sync::Arc, collections::{BTreeMap, BTreeSet}, fmt::Display};
 
 use chrono::NaiveDate;
 
+use crate::{ast::Expression, columnar::ColumnarTable, visitor::referenced_identifiers};
+
+
+/// The largest integer that an `f64` can represent exactly. Whole numbers up to this size are written to JSON as
+/// integers.
+pub(crate) const MAX_EXACT_INTEGER: f64 = 9007199254740992.0;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataValue {
@@ -15,21 +21,31 @@ pub enum DataValue {
     Hyperlink(Arc<str>),
     Media(Arc<str>),
     Record(Record),
-    Table(Vec<Record>),
+    Table(Table),
+    /// An untyped object, such as the result of `ParseJSON`, which is navigated with `.` and converted with
+    /// functions like `Text` and `Value`. JSON `null` is never wrapped, and is Blank instead.
+    Untyped(Arc<serde_json::Value>),
+    /// A named value of an enum, such as `JSONFormat.IndentFour`.
+    Enum(EnumValue),
     Blank,
 }
 
 impl DataValue {
     pub fn is_blank(&self) -> bool {
-        match self {
-            DataValue::Blank => true,
-            _ => false,
-        }
+        matches!(self, DataValue::Blank)
     }
 
     pub fn is_not_blank(&self) -> bool {
         !self.is_blank()
     }
+
+    /// Wraps a JSON value as an untyped object.
+    pub fn untyped(value: serde_json::Value) -> DataValue {
+        match value {
+            serde_json::Value::Null => DataValue::Blank,
+            v => DataValue::Untyped(Arc::new(v)),
+        }
+    }
 }
 
 impl Display for DataValue {
@@ -38,30 +54,365 @@ impl Display for DataValue {
             DataValue::Date(d) => write!(f, "{}", d),
             DataValue::Number(n) => write!(f, "{}", n),
             DataValue::Boolean(b) => write!(f, "{}", b),
-            DataValue::OptionSet(o) => write!(f, "{:?}", o),
+            DataValue::OptionSet(o) => write!(f, "{}", o.display_name()),
             DataValue::Text(t) => write!(f, "{}", t),
             DataValue::Image(i) => write!(f, "{}", i),
             DataValue::Hyperlink(h) => write!(f, "{}", h),
             DataValue::Media(m) => write!(f, "{}", m),
             DataValue::Record(r) => write!(f, "{:?}", r),
             DataValue::Table(t) => write!(f, "{:?}", t),
+            DataValue::Untyped(u) => write!(f, "{}", u),
+            DataValue::Enum(e) => write!(f, "{}.{}", e.enum_name, e.name),
             DataValue::Blank => write!(f, ""),
         }
     }
 }
 
+/// The type of a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataType {
+    Number,
+    Text,
+    Boolean,
+    Date,
+    Record(Arc<Schema>),
+    Table(Arc<Schema>),
+    Untyped,
+    /// A value of the named enum.
+    Enum(Arc<str>),
+    /// An option of the named option set.
+    OptionSet(Arc<str>),
+}
+
+/// The names and types of the fields of a record, or of the columns of a table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    fields: Vec<(Arc<str>, DataType)>,
+    index: BTreeMap<Arc<str>, usize>,
+}
+
+impl Schema {
+    pub fn new(fields: Vec<(Arc<str>, DataType)>) -> Schema {
+        let index = fields.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect();
+        Schema { fields, index }
+    }
+
+    pub fn fields(&self) -> &[(Arc<str>, DataType)] {
+        &self.fields
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DataType> {
+        self.position(name).map(|i| &self.fields[i].1)
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+}
+
+/// A value of an enum. Two values are equal if they have the same enum and name.
+#[derive(Debug, Clone)]
+pub struct EnumValue {
+    pub enum_name: Arc<str>,
+    pub name: Arc<str>,
+    /// The value that the enum value stands for, such as the flag that `JSON` reads from a `JSONFormat` value.
+    pub value: Arc<DataValue>,
+}
+
+impl PartialEq for EnumValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.enum_name == other.enum_name && self.name == other.name
+    }
+}
+
+/// An enum that formulas can refer to by name, such as `SortOrder`, with its named values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumType {
+    name: Arc<str>,
+    values: Vec<EnumValue>,
+}
+
+impl EnumType {
+    pub fn new(name: &str, values: Vec<(&str, DataValue)>) -> EnumType {
+        let enum_name: Arc<str> = Arc::from(name);
+        EnumType {
+            values: values.into_iter().map(|(value_name, value)| EnumValue {
+                enum_name: enum_name.clone(),
+                name: Arc::from(value_name),
+                value: Arc::new(value),
+            }).collect(),
+            name: enum_name,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn values(&self) -> &[EnumValue] {
+        &self.values
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EnumValue> {
+        self.values.iter().find(|v| v.name.as_ref() == name)
+    }
+}
+
+/// An option set that formulas can refer to by name, such as `Status`, with its options.
+#[derive(Debug, PartialEq)]
+pub struct OptionSetType {
+    name: Arc<str>,
+    members: Vec<OptionSetMember>,
+}
+
+/// One of the options of an option set.
 #[derive(Debug, Clone, PartialEq)]
+pub struct OptionSetMember {
+    /// The name that formulas use, as in `Status.OnHold`.
+    pub name: Arc<str>,
+    /// The number stored for the option, such as a Dataverse choice value.
+    pub value: i64,
+    /// The name shown to users, such as `On Hold`, which `Text` returns.
+    pub display_name: Arc<str>,
+}
+
+impl OptionSetType {
+    /// Creates an option set from the name, number and display name of each option.
+    pub fn new(name: &str, members: Vec<(&str, i64, &str)>) -> OptionSetType {
+        OptionSetType {
+            name: Arc::from(name),
+            members: members.into_iter().map(|(name, value, display_name)| OptionSetMember {
+                name: Arc::from(name),
+                value,
+                display_name: Arc::from(display_name),
+            }).collect(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn members(&self) -> &[OptionSetMember] {
+        &self.members
+    }
+
+    /// Selects the option with the given name.
+    pub fn option(self: &Arc<Self>, name: &str) -> Option<OptionSet> {
+        self.members.iter().position(|m| m.name.as_ref() == name).map(|index| OptionSet { set: self.clone(), index })
+    }
+}
+
+/// A selected option of an option set, such as `Status.Active`. Two options are equal if they have the same name
+/// and belong to option sets of the same name.
+#[derive(Debug, Clone)]
 pub struct OptionSet {
-    pub options: BTreeMap<i64, String>,
+    set: Arc<OptionSetType>,
+    index: usize,
+}
+
+impl OptionSet {
+    pub fn option_set(&self) -> &Arc<OptionSetType> {
+        &self.set
+    }
+
+    pub fn member(&self) -> &OptionSetMember {
+        &self.set.members[self.index]
+    }
+
+    pub fn name(&self) -> &str {
+        &self.member().name
+    }
+
+    pub fn value(&self) -> i64 {
+        self.member().value
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.member().display_name
+    }
+}
+
+impl PartialEq for OptionSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.set.name == other.set.name && self.name() == other.name()
+    }
 }
 
+/// A record is a set of named fields. The fields are shared between clones of the record, and are only copied
+/// when a shared record is modified.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Record {
-    pub fields: BTreeMap<Arc<str>, DataValue>
+    pub fields: Arc<BTreeMap<Arc<str>, DataValue>>
 }
 
 impl Record {
+    pub fn new(fields: BTreeMap<Arc<str>, DataValue>) -> Record {
+        Record { fields: Arc::new(fields) }
+    }
+
     pub fn from(fields: Vec<(Arc<str>, DataValue)>) -> Record {
-        Record { fields: fields.into_iter().collect::<BTreeMap<Arc<str>, DataValue>>() }
+        Record::new(fields.into_iter().collect::<BTreeMap<Arc<str>, DataValue>>())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DataValue> {
+        self.fields.get(name)
+    }
+
+    /// Sets a field, copying the fields first if they are shared with another record.
+    pub fn set(&mut self, name: &str, value: DataValue) {
+        Arc::make_mut(&mut self.fields).insert(Arc::from(name), value);
+    }
+}
+
+/// A table is an ordered list of records. It is stored either as rows, or as typed columns for large datasets.
+/// The storage is shared between clones of the table, so cloning a table is O(1), and is only copied when a
+/// shared table is modified.
+#[derive(Debug, Clone)]
+pub struct Table {
+    data: TableData,
+    /// Whether the table was built from a list of plain values, each of which is the `Value` field of a row, as in a
+    /// Power Fx single column table. Such tables are serialized back to a list of the values.
+    values: bool,
+}
+
+#[derive(Debug, Clone)]
+enum TableData {
+    Rows(Arc<Vec<Record>>),
+    Columns(Arc<ColumnarTable>),
+}
+
+impl Table {
+    pub fn new(rows: Vec<Record>) -> Table {
+        Table { data: TableData::Rows(Arc::new(rows)), values: false }
+    }
+
+    /// Creates a single column table with a row for each value, in a field named `Value`.
+    pub fn from_values(values: Vec<DataValue>) -> Table {
+        let rows = values.into_iter().map(|value| Record::from(vec![(Arc::from("Value"), value)])).collect();
+        Table { data: TableData::Rows(Arc::new(rows)), values: true }
+    }
+
+    /// Whether the table was created from plain values with `from_values`, and still only has their rows.
+    pub fn is_values(&self) -> bool {
+        self.values
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.data {
+            TableData::Rows(rows) => rows.len(),
+            TableData::Columns(columns) => columns.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the columnar storage of the table, if it has any.
+    pub fn as_columnar(&self) -> Option<&ColumnarTable> {
+        match &self.data {
+            TableData::Rows(_) => None,
+            TableData::Columns(columns) => Some(columns),
+        }
+    }
+
+    /// Returns the row at `index`. Rows share their fields with the table, or are materialised from a columnar table.
+    pub fn get(&self, index: usize) -> Option<Record> {
+        if index >= self.len() {
+            return None;
+        }
+        Some(self.row(index))
+    }
+
+    pub fn first(&self) -> Option<Record> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<Record> {
+        self.len().checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    /// Iterates over the rows of the table, each of which shares its fields with the table or is materialised
+    /// from a columnar table.
+    pub fn iter(&self) -> impl Iterator<Item = Record> + '_ {
+        (0..self.len()).map(|i| self.row(i))
+    }
+
+    /// Iterates over the rows of the table as the scopes in which `expressions` are evaluated. Rows of a
+    /// columnar table only have the columns that the expressions refer to.
+    pub(crate) fn scopes_for<'a>(&'a self, expressions: &[Expression]) -> Box<dyn Iterator<Item = Record> + 'a> {
+        match &self.data {
+            TableData::Rows(rows) => Box::new(rows.iter().cloned()),
+            TableData::Columns(columns) => {
+                let mut identifiers = BTreeSet::new();
+                for expression in expressions {
+                    identifiers.extend(referenced_identifiers(expression));
+                }
+                let positions = identifiers.iter().filter_map(|name| columns.schema().position(name)).collect::<Vec<_>>();
+                Box::new((0..columns.len()).map(move |i| columns.partial_row(i, positions.iter().copied())))
+            },
+        }
+    }
+
+    /// Creates a table with the rows at `indices`, keeping the storage layout of this table.
+    pub(crate) fn select(&self, indices: &[usize]) -> Table {
+        match &self.data {
+            TableData::Rows(rows) => Table { values: self.values, ..Table::new(indices.iter().map(|i| rows[*i].clone()).collect()) },
+            TableData::Columns(columns) => Table::from(columns.select(indices)),
+        }
     }
-}
\ No newline at end of file
+
+    /// Appends a row, copying the rows first if they are shared with another table. A columnar table is
+    /// converted to rows.
+    pub fn push(&mut self, record: Record) {
+        self.values &= record.fields.len() == 1 && record.fields.contains_key("Value");
+        if let TableData::Columns(_) = self.data {
+            self.data = TableData::Rows(Arc::new(self.iter().collect()));
+        }
+        if let TableData::Rows(rows) = &mut self.data {
+            Arc::make_mut(rows).push(record);
+        }
+    }
+
+    fn row(&self, index: usize) -> Record {
+        match &self.data {
+            TableData::Rows(rows) => rows[index].clone(),
+            TableData::Columns(columns) => columns.row(index),
+        }
+    }
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Table::new(Vec::new())
+    }
+}
+
+impl PartialEq for Table {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.data, &other.data) {
+            (TableData::Rows(a), TableData::Rows(b)) => a == b,
+            (TableData::Columns(a), TableData::Columns(b)) if a == b => true,
+            _ => self.len() == other.len() && self.iter().eq(other.iter()),
+        }
+    }
+}
+
+impl From<Vec<Record>> for Table {
+    fn from(rows: Vec<Record>) -> Self {
+        Table::new(rows)
+    }
+}
+
+impl From<ColumnarTable> for Table {
+    fn from(columns: ColumnarTable) -> Self {
+        Table { data: TableData::Columns(Arc::new(columns)), values: false }
+    }
+}
+
+impl FromIterator<Record> for Table {
+    fn from_iter<T: IntoIterator<Item = Record>>(iter: T) -> Self {
+        Table::new(iter.into_iter().collect())
+    }
+}
@@ -0,0 +1,143 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use crate::{ast::Expression, convert::PowerFxValue, evaluator::{EvaluationError, ExpressionEvaluator, Session}, function_registry::{FunctionKind, ScalarFunction, Signature, ValueType}, models::{DataType, DataValue}};
+
+
+/// A Rust closure or function that can be registered with `PowerFxEngine::register_fn`.
+///
+/// This is implemented for anything callable with up to eight arguments that implement `PowerFxValue`, returning
+/// a `PowerFxValue` or a `Result` of one. Arguments are evaluated and converted before the closure is called, and
+/// trailing `Option` arguments that are left out are passed as `None`.
+pub trait IntoScalarFunction<Args>: Send + Sync + 'static {
+    /// Wraps the closure as the function `name`, described by `description` and of the given kind.
+    fn into_scalar_function(self, name: &str, description: &str, kind: FunctionKind, evaluator: Arc<ExpressionEvaluator>) -> Arc<dyn ScalarFunction>;
+}
+
+/// The return type of a closure registered with `PowerFxEngine::register_fn`.
+pub trait FunctionResult {
+    fn value_type() -> ValueType;
+
+    fn into_result(self) -> Result<DataValue, EvaluationError>;
+}
+
+impl<T: PowerFxValue> FunctionResult for T {
+    fn value_type() -> ValueType {
+        ValueType::from(&T::data_type())
+    }
+
+    fn into_result(self) -> Result<DataValue, EvaluationError> {
+        Ok(self.into_data_value())
+    }
+}
+
+impl<T: PowerFxValue> FunctionResult for Result<T, EvaluationError> {
+    fn value_type() -> ValueType {
+        ValueType::from(&T::data_type())
+    }
+
+    fn into_result(self) -> Result<DataValue, EvaluationError> {
+        self.map(PowerFxValue::into_data_value)
+    }
+}
+
+impl From<&DataType> for ValueType {
+    fn from(data_type: &DataType) -> Self {
+        match data_type {
+            DataType::Number => ValueType::Number,
+            DataType::Text => ValueType::Text,
+            DataType::Boolean => ValueType::Boolean,
+            DataType::Date => ValueType::Date,
+            DataType::Record(_) => ValueType::Record,
+            DataType::Table(_) => ValueType::Table,
+            DataType::Untyped => ValueType::Untyped,
+            DataType::Enum(_) => ValueType::Enum,
+            DataType::OptionSet(_) => ValueType::OptionSet,
+        }
+    }
+}
+
+struct NativeFunction<F, Args> {
+    name: String,
+    description: String,
+    kind: FunctionKind,
+    function: F,
+    evaluator: Arc<ExpressionEvaluator>,
+    args: PhantomData<fn() -> Args>,
+}
+
+/// Evaluates the argument at `index` and converts it, naming the function and argument if it has the wrong type.
+/// A trailing optional argument that was left out converts from Blank.
+fn argument<T: PowerFxValue>(function: &str, evaluator: &ExpressionEvaluator, context: &mut Session, args: &[Expression], index: usize) -> Result<T, EvaluationError> {
+    let value = match args.get(index) {
+        Some(arg) => evaluator.evaluate_expression(context, arg)?,
+        None => DataValue::Blank,
+    };
+    T::from_data_value(value).map_err(|e| match e {
+        EvaluationError::InvalidArgument(message) => EvaluationError::InvalidArgument(format!("Argument {} of {}: {}", index + 1, function, message)),
+        e => e,
+    })
+}
+
+macro_rules! impl_into_scalar_function {
+    ($($arg:ident: $index:tt),*) => {
+        impl<F, R, $($arg,)*> IntoScalarFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: FunctionResult + 'static,
+            $($arg: PowerFxValue + 'static,)*
+        {
+            fn into_scalar_function(self, name: &str, description: &str, kind: FunctionKind, evaluator: Arc<ExpressionEvaluator>) -> Arc<dyn ScalarFunction> {
+                Arc::new(NativeFunction::<F, ($($arg,)*)> {
+                    name: name.to_string(),
+                    description: description.to_string(),
+                    kind,
+                    function: self,
+                    evaluator,
+                    args: PhantomData,
+                })
+            }
+        }
+
+        impl<F, R, $($arg,)*> ScalarFunction for NativeFunction<F, ($($arg,)*)>
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: FunctionResult + 'static,
+            $($arg: PowerFxValue + 'static,)*
+        {
+            #[allow(unused_variables)]
+            fn call(&self, context: &mut Session, args: &Vec<Expression>) -> Result<DataValue, EvaluationError> {
+                (self.function)($(argument::<$arg>(&self.name, &self.evaluator, context, args, $index)?),*).into_result()
+            }
+
+            #[allow(unused_variables)]
+            fn signature(&self) -> Signature {
+                // Only the `Option` parameters after the last required one can be left out.
+                let optional: &[bool] = &[$($arg::is_optional()),*];
+                let required = optional.iter().rposition(|optional| !optional).map_or(0, |i| i + 1);
+                let signature = Signature::new(&self.description);
+                $(
+                    let name = format!("arg{}", $index + 1);
+                    let signature = if $index < required {
+                        signature.param(&name, ValueType::from(&$arg::data_type()))
+                    } else {
+                        signature.optional_param(&name, ValueType::from(&$arg::data_type()))
+                    };
+                )*
+                Signature {
+                    kind: self.kind,
+                    ..signature.returns(R::value_type())
+                }
+            }
+        }
+    };
+}
+
+impl_into_scalar_function!();
+impl_into_scalar_function!(A1: 0);
+impl_into_scalar_function!(A1: 0, A2: 1);
+impl_into_scalar_function!(A1: 0, A2: 1, A3: 2);
+impl_into_scalar_function!(A1: 0, A2: 1, A3: 2, A4: 3);
+impl_into_scalar_function!(A1: 0, A2: 1, A3: 2, A4: 3, A5: 4);
+impl_into_scalar_function!(A1: 0, A2: 1, A3: 2, A4: 3, A5: 4, A6: 5);
+impl_into_scalar_function!(A1: 0, A2: 1, A3: 2, A4: 3, A5: 4, A6: 5, A7: 6);
+impl_into_scalar_function!(A1: 0, A2: 1, A3: 2, A4: 3, A5: 4, A6: 5, A7: 6, A8: 7);
@@ -0,0 +1,449 @@
+use crate::ast::{self, BinaryExpression, Comment, CommentKind, Context, Expression, Formula, Literal, Trivia, UnaryExpression};
+use crate::evaluator::EvaluationError;
+use crate::lexer::{self, DecimalSeparator};
+use crate::visitor::{self, RenameOptions};
+
+
+// How tightly each kind of expression binds, from loosest to tightest, following the precedence table in the lexer.
+const ALIAS: u8 = 0;
+const LOGICAL: u8 = 1;
+const NOT: u8 = 2;
+const COMPARISON: u8 = 3;
+const CONCAT: u8 = 4;
+const ADDITIVE: u8 = 5;
+const MULTIPLICATIVE: u8 = 6;
+const EXPONENT: u8 = 7;
+const NEGATE: u8 = 8;
+const PERCENT: u8 = 9;
+const MEMBER: u8 = 10;
+const ATOM: u8 = 11;
+
+// The indent used for calls that a `//` comment forces onto several lines when no layout is given.
+const DEFAULT_INDENT: usize = 4;
+
+const KEYWORDS: &[&str] = &["true", "false", "And", "Or", "Not", "in", "exactin", "as", "AS", "Parent", "Self", "ThisItem", "ThisRecord"];
+
+// The keywords that are read as a literal wherever they appear, even before `(`.
+const LITERAL_KEYWORDS: &[&str] = &["true", "false"];
+
+/// Options for `format_formula`.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// The width that lines are kept within where possible. Calls and records that do not fit are split over
+    /// several lines, one argument or field per line.
+    pub max_width: usize,
+    /// The number of spaces that each level of nesting is indented by.
+    pub indent: usize,
+    pub decimal_separator: DecimalSeparator,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            max_width: 80,
+            indent: DEFAULT_INDENT,
+            decimal_separator: DecimalSeparator::Dot,
+        }
+    }
+}
+
+/// Rewrites a formula written with one decimal separator so that it uses the other. The formula is parsed and
+/// written back out, so its layout is not kept, but its comments are: comments around an argument or a chained
+/// expression stay there, and any others are written before the expression they appeared in. A `//` comment inside a
+/// call puts the call's arguments on separate lines.
+pub fn convert_formula(formula: &str, from: DecimalSeparator, to: DecimalSeparator) -> Result<String, EvaluationError> {
+    let formula = lexer::parse_formula(formula, from)?;
+    Ok(Printer::new(to).print_formula(&formula))
+}
+
+/// Lays out a formula in canonical form: chained formulas on separate lines, and calls and records that are too
+/// long for one line split over several, with their arguments indented. Comments are kept as in `convert_formula`.
+pub fn format_formula(formula: &str, options: &FormatOptions) -> Result<String, EvaluationError> {
+    let formula = lexer::parse_formula(formula, options.decimal_separator)?;
+    let printer = Printer {
+        separator: options.decimal_separator,
+        layout: Some(Layout { max_width: options.max_width, indent: options.indent }),
+    };
+    Ok(printer.print_formula(&formula))
+}
+
+/// Renames the variable or field `from` to `to` throughout a formula, as `rename_identifier` does for an expression.
+/// The formula is written back out as in `convert_formula`.
+pub fn rename_in_formula(formula: &str, from: &str, to: &str, separator: DecimalSeparator, options: &RenameOptions) -> Result<String, EvaluationError> {
+    let mut formula = lexer::parse_formula(formula, separator)?;
+    for expression in &mut formula.expressions {
+        visitor::rename_identifier(expression, from, to, options);
+    }
+    Ok(Printer::new(separator).print_formula(&formula))
+}
+
+/// Writes expressions as formula text, with only the parentheses that their structure needs.
+pub(crate) struct Printer {
+    separator: DecimalSeparator,
+    /// How to split long expressions over lines, or `None` to write each expression on one line.
+    layout: Option<Layout>,
+}
+
+#[derive(Clone, Copy)]
+struct Layout {
+    max_width: usize,
+    indent: usize,
+}
+
+impl Printer {
+    pub(crate) fn new(separator: DecimalSeparator) -> Printer {
+        Printer { separator, layout: None }
+    }
+
+    pub(crate) fn print_formula(&self, formula: &Formula) -> String {
+        let mut out = String::new();
+        let mut comments = formula.comments.iter().peekable();
+        let no_trivia = Trivia::default();
+        let trivia = |i: usize| formula.trivia.get(i).unwrap_or(&no_trivia);
+
+        let mut line_ended = false;
+        for (i, (expression, span)) in formula.expressions.iter().zip(&formula.spans).enumerate() {
+            if i > 0 {
+                out.push_str(self.separator.chain_separator());
+                line_ended = write_trailing(&mut out, &trivia(i - 1).trailing, "");
+                out.push(if self.layout.is_some() || line_ended { '\n' } else { ' ' });
+            }
+            let leading = trivia(i).leading.iter();
+            let inner = std::iter::from_fn(|| comments.next_if(|c| c.span.start < span.end));
+            for comment in leading.chain(inner) {
+                write_comment(&mut out, comment);
+                out.push(if comment.kind == CommentKind::Line { '\n' } else { ' ' });
+            }
+            self.write_expression(&mut out, expression, ALIAS, 0);
+        }
+        if let Some(last) = formula.expressions.len().checked_sub(1) {
+            line_ended = write_trailing(&mut out, &trivia(last).trailing, "");
+        }
+
+        for comment in comments {
+            if line_ended {
+                out.push('\n');
+            } else if !out.is_empty() {
+                out.push(' ');
+            }
+            write_comment(&mut out, comment);
+            line_ended = comment.kind == CommentKind::Line;
+        }
+
+        out
+    }
+
+    pub(crate) fn print_expression(&self, expression: &Expression) -> String {
+        let mut out = String::new();
+        self.write_expression(&mut out, expression, ALIAS, 0);
+        out
+    }
+
+    /// Writes `expression`, in parentheses if it binds more loosely than `min_precedence`. `depth` is the level of
+    /// nesting that lines are indented to if the expression is split.
+    fn write_expression(&self, out: &mut String, expression: &Expression, min_precedence: u8, depth: usize) {
+        let parenthesized = precedence(expression) < min_precedence;
+        if parenthesized {
+            out.push('(');
+        }
+
+        match expression {
+            Expression::UnaryExpression(UnaryExpression::Literal(Literal::Record(record))) => {
+                let split = self.is_too_long(out, expression);
+                self.write_record(out, record, depth, split);
+            },
+            Expression::UnaryExpression(expression) => self.write_unary_expression(out, expression, depth),
+            Expression::BinaryExpression(expression) => self.write_binary_expression(out, expression, depth),
+            Expression::FunctionExpression(func) => {
+                let split = func.trivia.iter().any(Trivia::has_line_comment) || self.is_too_long(out, expression);
+                self.write_call(out, &func.name, &func.args, &func.trivia, depth, split);
+            },
+        }
+
+        if parenthesized {
+            out.push(')');
+        }
+    }
+
+    /// Whether `expression`, written on one line from the end of `out`, would go past the maximum width.
+    fn is_too_long(&self, out: &str, expression: &Expression) -> bool {
+        match self.layout {
+            Some(layout) => {
+                let column = out.rsplit('\n').next().unwrap_or_default().chars().count();
+                let flat = Printer::new(self.separator).print_expression(expression);
+                column + flat.chars().count() > layout.max_width
+            },
+            None => false,
+        }
+    }
+
+    fn write_unary_expression(&self, out: &mut String, expression: &UnaryExpression, depth: usize) {
+        match expression {
+            UnaryExpression::Not(e) => {
+                out.push_str("Not ");
+                self.write_expression(out, e, NOT, depth);
+            },
+            UnaryExpression::Negate(e) => {
+                out.push('-');
+                self.write_expression(out, e, NEGATE + 1, depth);
+            },
+            UnaryExpression::Percent(e) => {
+                self.write_expression(out, e, PERCENT + 1, depth);
+                out.push('%');
+            },
+            UnaryExpression::IsBlank(e) => self.write_call(out, "IsBlank", std::slice::from_ref(e), &[], depth, false),
+            UnaryExpression::IsNotBlank(e) => {
+                out.push_str("Not ");
+                self.write_call(out, "IsBlank", std::slice::from_ref(e), &[], depth, false);
+            },
+            UnaryExpression::Literal(literal) => self.write_literal(out, literal),
+            UnaryExpression::Property { context, key } => {
+                out.push_str(match context {
+                    Context::Parent => "Parent",
+                    Context::Self_ => "Self",
+                    Context::ThisItem => "ThisItem",
+                    Context::ThisRecord => "ThisRecord",
+                });
+                out.push('.');
+                write_name(out, key);
+            },
+            UnaryExpression::Parameter(name) |
+            UnaryExpression::Identifier(name) => write_name(out, name),
+            UnaryExpression::Alias { source, alias } => {
+                self.write_expression(out, source, LOGICAL, depth);
+                out.push_str(" as ");
+                write_name(out, alias);
+            },
+            UnaryExpression::Member { source, member } => {
+                self.write_expression(out, source, MEMBER, depth);
+                out.push('.');
+                write_name(out, member);
+            },
+        }
+    }
+
+    fn write_binary_expression(&self, out: &mut String, expression: &BinaryExpression, depth: usize) {
+        let (a, operator, b) = match expression {
+            BinaryExpression::And(a, b) => (a, "And", b),
+            BinaryExpression::Or(a, b) => (a, "Or", b),
+            BinaryExpression::Eq(a, b) => (a, "=", b),
+            BinaryExpression::Ne(a, b) => (a, "<>", b),
+            BinaryExpression::Lt(a, b) => (a, "<", b),
+            BinaryExpression::Le(a, b) => (a, "<=", b),
+            BinaryExpression::Gt(a, b) => (a, ">", b),
+            BinaryExpression::Ge(a, b) => (a, ">=", b),
+            BinaryExpression::In(a, b, false) => (a, "in", b),
+            BinaryExpression::In(a, b, true) => (a, "exactin", b),
+            BinaryExpression::Concat(a, b) => (a, "&", b),
+            BinaryExpression::Add(a, b) => (a, "+", b),
+            BinaryExpression::Subtract(a, b) => (a, "-", b),
+            BinaryExpression::Multiply(a, b) => (a, "*", b),
+            BinaryExpression::Divide(a, b) => (a, "/", b),
+            BinaryExpression::Exponent(a, b) => (a, "^", b),
+        };
+
+        // Binary operators are left associative, so only the right operand needs parentheses at the same level.
+        let level = binary_precedence(expression);
+        self.write_expression(out, a, level, depth);
+        out.push(' ');
+        out.push_str(operator);
+        out.push(' ');
+        self.write_expression(out, b, level + 1, depth);
+    }
+
+    fn write_call(&self, out: &mut String, name: &str, args: &[Expression], trivia: &[Trivia], depth: usize, split: bool) {
+        // Operator keywords such as `Not` are also function names, and are only quoted where they would be read as a
+        // literal instead of a call.
+        for (i, part) in name.split('.').enumerate() {
+            if i > 0 {
+                out.push('.');
+            }
+            if is_plain_name(part) && !LITERAL_KEYWORDS.contains(&part) {
+                out.push_str(part);
+            } else {
+                write_quoted_name(out, part);
+            }
+        }
+        out.push('(');
+        self.write_list(out, args, trivia, depth, split, |out, arg, depth| self.write_expression(out, arg, ALIAS, depth));
+        out.push(')');
+    }
+
+    fn write_literal(&self, out: &mut String, literal: &Literal) {
+        match literal {
+            Literal::Number(n) => out.push_str(&n.to_string().replace('.', self.separator.decimal_separator())),
+            Literal::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+            Literal::Text(t) |
+            Literal::Image(t) |
+            Literal::Hyperlink(t) |
+            Literal::Media(t) => write_text(out, t),
+            Literal::Date(d) => {
+                let parts = d.format("%Y %-m %-d").to_string();
+                out.push_str("Date(");
+                out.push_str(&parts.replace(' ', &format!("{} ", self.separator.list_separator())));
+                out.push(')');
+            },
+            Literal::Record(record) => self.write_record(out, record, 0, false),
+            Literal::Table(records) => {
+                out.push_str("Table(");
+                self.write_list(out, records, &[], 0, false, |out, record, depth| self.write_record(out, record, depth, false));
+                out.push(')');
+            },
+            Literal::Blank => out.push_str("Blank()"),
+        }
+    }
+
+    fn write_record(&self, out: &mut String, record: &ast::Record, depth: usize, split: bool) {
+        if record.fields.is_empty() {
+            out.push_str("{}");
+            return;
+        }
+        let fields: Vec<_> = record.fields.iter().collect();
+        out.push('{');
+        if !split {
+            out.push(' ');
+        }
+        self.write_list(out, &fields, &[], depth, split, |out, (name, value), depth| {
+            write_name(out, name);
+            out.push_str(": ");
+            self.write_expression(out, value, ALIAS, depth);
+        });
+        if !split {
+            out.push(' ');
+        }
+        out.push('}');
+    }
+
+    /// Writes the items of a list separated by the list separator, with the comments in `trivia` around them. If
+    /// `split` is set, each item goes on its own line, indented one level deeper than `depth`, and the list ends on a
+    /// new line at `depth`.
+    fn write_list<T>(&self, out: &mut String, items: &[T], trivia: &[Trivia], depth: usize, split: bool, write: impl Fn(&mut String, &T, usize)) {
+        let split = split && !items.is_empty();
+        let indent = self.layout.map_or(DEFAULT_INDENT, |layout| layout.indent);
+        let item_indent = " ".repeat(indent * (depth + 1));
+        let item_depth = if split { depth + 1 } else { depth };
+        let no_trivia = Trivia::default();
+        let trivia = |i: usize| trivia.get(i).unwrap_or(&no_trivia);
+
+        let mut line_ended = false;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                out.push_str(self.separator.list_separator());
+                line_ended = write_trailing(out, &trivia(i - 1).trailing, &item_indent);
+            }
+            if split || line_ended {
+                out.push('\n');
+                out.push_str(&item_indent);
+            } else if i > 0 {
+                out.push(' ');
+            }
+            for comment in &trivia(i).leading {
+                write_comment(out, comment);
+                if split || comment.kind == CommentKind::Line {
+                    out.push('\n');
+                    out.push_str(&item_indent);
+                } else {
+                    out.push(' ');
+                }
+            }
+            write(out, item, item_depth);
+        }
+        if let Some(last) = items.len().checked_sub(1) {
+            line_ended = write_trailing(out, &trivia(last).trailing, &item_indent);
+        }
+        if split || line_ended {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent * depth));
+        }
+    }
+}
+
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::BinaryExpression(expression) => binary_precedence(expression),
+        Expression::UnaryExpression(expression) => match expression {
+            UnaryExpression::Alias { .. } => ALIAS,
+            UnaryExpression::Not(_) | UnaryExpression::IsNotBlank(_) => NOT,
+            UnaryExpression::Negate(_) => NEGATE,
+            UnaryExpression::Percent(_) => PERCENT,
+            UnaryExpression::Member { .. } => MEMBER,
+            _ => ATOM,
+        },
+        Expression::FunctionExpression(_) => ATOM,
+    }
+}
+
+fn binary_precedence(expression: &BinaryExpression) -> u8 {
+    match expression {
+        BinaryExpression::And(..) | BinaryExpression::Or(..) => LOGICAL,
+        BinaryExpression::Eq(..) |
+        BinaryExpression::Ne(..) |
+        BinaryExpression::Lt(..) |
+        BinaryExpression::Le(..) |
+        BinaryExpression::Gt(..) |
+        BinaryExpression::Ge(..) |
+        BinaryExpression::In(..) => COMPARISON,
+        BinaryExpression::Concat(..) => CONCAT,
+        BinaryExpression::Add(..) | BinaryExpression::Subtract(..) => ADDITIVE,
+        BinaryExpression::Multiply(..) | BinaryExpression::Divide(..) => MULTIPLICATIVE,
+        BinaryExpression::Exponent(..) => EXPONENT,
+    }
+}
+
+/// Writes a name as is if it can be, or else in single quotes.
+fn write_name(out: &mut String, name: &str) {
+    if is_plain_name(name) && !KEYWORDS.contains(&name) {
+        out.push_str(name);
+    } else {
+        write_quoted_name(out, name);
+    }
+}
+
+/// Whether a name is made only of the characters that names can be written with outside of quotes.
+fn is_plain_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn write_quoted_name(out: &mut String, name: &str) {
+    out.push('\'');
+    out.push_str(&name.replace('\'', "''"));
+    out.push('\'');
+}
+
+fn write_text(out: &mut String, text: &str) {
+    out.push('"');
+    out.push_str(&text.replace('"', "\"\""));
+    out.push('"');
+}
+
+/// Writes comments that follow an item on the same line, and returns whether a `//` comment ended the line. Any
+/// comment after a `//` comment goes on a new line at `indent`.
+fn write_trailing(out: &mut String, comments: &[Comment], indent: &str) -> bool {
+    let mut line_ended = false;
+    for comment in comments {
+        if line_ended {
+            out.push('\n');
+            out.push_str(indent);
+        } else {
+            out.push(' ');
+        }
+        write_comment(out, comment);
+        line_ended = comment.kind == CommentKind::Line;
+    }
+    line_ended
+}
+
+fn write_comment(out: &mut String, comment: &Comment) {
+    match comment.kind {
+        CommentKind::Line => {
+            out.push_str("//");
+            out.push_str(&comment.text);
+        },
+        CommentKind::Block => {
+            out.push_str("/*");
+            out.push_str(&comment.text);
+            out.push_str("*/");
+        },
+    }
+}
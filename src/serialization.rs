@@ -0,0 +1,216 @@
+//! Conversion between `DataValue` and serde, enabled by the `serde` feature.
+//!
+//! Values map to JSON as follows:
+//!
+//! | DataValue                        | JSON                                               |
+//! |----------------------------------|----------------------------------------------------|
+//! | `Blank`                          | `null`                                             |
+//! | `Boolean`                        | boolean                                            |
+//! | `Number`                         | number, written as an integer if it has no fraction |
+//! | `Text`, `Hyperlink`, `Image`, `Media` | string                                        |
+//! | `Date`                           | string in `YYYY-MM-DD` format                      |
+//! | `Record`                         | object                                             |
+//! | `Table`                          | array of objects, or of values for `Table::from_values` |
+//! | `OptionSet`                      | string with the name of the option                 |
+//! | `Untyped`                        | the wrapped JSON                                   |
+//! | `Enum`                           | the value that the enum value stands for           |
+//!
+//! When deserializing, strings always become `Text`, objects become `Record` and arrays become `Table`. Array
+//! elements that are not objects become records with a single `Value` field, as in a Power Fx single column table. A
+//! table of only such values is serialized back to an array of the values, so that `Vec<u32>` and other arrays
+//! round-trip, while a table of records is always serialized as an array of objects, even if its only field is `Value`. An
+//! `OptionSet` value cannot be deserialized on its own, as the name does not say which option set it belongs to.
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use serde::{de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor}, ser::{SerializeMap, SerializeSeq}, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{evaluator::EvaluationError, models::{DataValue, OptionSet, Record, Table, MAX_EXACT_INTEGER}};
+
+
+/// Converts any serializable value, such as a host struct, into a `DataValue`.
+pub fn to_data_value<T: Serialize>(value: &T) -> Result<DataValue, EvaluationError> {
+    let json = serde_json::to_value(value).map_err(|e| EvaluationError::InvalidArgument(e.to_string()))?;
+    DataValue::deserialize(json).map_err(|e| EvaluationError::InvalidArgument(e.to_string()))
+}
+
+/// Converts a `DataValue`, such as the result of a formula, into any deserializable value.
+pub fn from_data_value<T: DeserializeOwned>(value: &DataValue) -> Result<T, EvaluationError> {
+    let json = serde_json::to_value(value).map_err(|e| EvaluationError::InvalidArgument(e.to_string()))?;
+    serde_json::from_value(json).map_err(|e| EvaluationError::InvalidArgument(e.to_string()))
+}
+
+impl Serialize for DataValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DataValue::Date(d) => serializer.collect_str(&d.format("%Y-%m-%d")),
+            DataValue::Number(n) => {
+                if n.fract() == 0.0 && n.abs() <= MAX_EXACT_INTEGER {
+                    serializer.serialize_i64(*n as i64)
+                } else {
+                    serializer.serialize_f64(*n)
+                }
+            },
+            DataValue::Boolean(b) => serializer.serialize_bool(*b),
+            DataValue::OptionSet(o) => o.serialize(serializer),
+            DataValue::Text(t) => serializer.serialize_str(t),
+            DataValue::Image(i) => serializer.serialize_str(i),
+            DataValue::Hyperlink(h) => serializer.serialize_str(h),
+            DataValue::Media(m) => serializer.serialize_str(m),
+            DataValue::Record(r) => r.serialize(serializer),
+            DataValue::Table(t) => t.serialize(serializer),
+            DataValue::Untyped(u) => u.serialize(serializer),
+            DataValue::Enum(e) => e.value.serialize(serializer),
+            DataValue::Blank => serializer.serialize_unit(),
+        }
+    }
+}
+
+impl Serialize for Record {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for (key, value) in self.fields.iter() {
+            map.serialize_entry(key.as_ref(), value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for Table {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for record in self.iter() {
+            match record.fields.get("Value") {
+                Some(value) if self.is_values() => seq.serialize_element(value)?,
+                _ => seq.serialize_element(&record)?,
+            }
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for OptionSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+struct DataValueVisitor;
+
+impl<'de> Visitor<'de> for DataValueVisitor {
+    type Value = DataValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a value that can be represented in Power Fx")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<DataValue, E> {
+        Ok(DataValue::Boolean(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<DataValue, E> {
+        Ok(DataValue::Number(v as f64))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<DataValue, E> {
+        Ok(DataValue::Number(v as f64))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<DataValue, E> {
+        Ok(DataValue::Number(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<DataValue, E> {
+        Ok(DataValue::Text(Arc::from(v)))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<DataValue, E> {
+        Ok(DataValue::Blank)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<DataValue, E> {
+        Ok(DataValue::Blank)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<DataValue, D::Error> {
+        DataValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<DataValue, A::Error> {
+        TableVisitor.visit_seq(seq).map(DataValue::Table)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<DataValue, A::Error> {
+        RecordVisitor.visit_map(map).map(DataValue::Record)
+    }
+}
+
+impl<'de> Deserialize<'de> for DataValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DataValueVisitor)
+    }
+}
+
+struct RecordVisitor;
+
+impl<'de> Visitor<'de> for RecordVisitor {
+    type Value = Record;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a record")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Record, A::Error> {
+        let mut fields = BTreeMap::new();
+        while let Some((key, value)) = map.next_entry::<String, DataValue>()? {
+            fields.insert(Arc::from(key), value);
+        }
+        Ok(Record::new(fields))
+    }
+}
+
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(RecordVisitor)
+    }
+}
+
+struct TableVisitor;
+
+impl<'de> Visitor<'de> for TableVisitor {
+    type Value = Table;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a table")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Table, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element::<DataValue>()? {
+            values.push(value);
+        }
+        if values.iter().any(|value| matches!(value, DataValue::Record(_))) {
+            let rows = values.into_iter().map(|value| match value {
+                DataValue::Record(r) => r,
+                v => Record::from(vec![(Arc::from("Value"), v)]),
+            });
+            return Ok(Table::new(rows.collect()));
+        }
+        Ok(Table::from_values(values))
+    }
+}
+
+impl<'de> Deserialize<'de> for Table {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(TableVisitor)
+    }
+}
+
+/// An option set value refers to the registered `OptionSetType` it belongs to, which its serialized name does not
+/// carry, so it cannot be deserialized on its own. Deserialize the name as a string instead, and look it up with
+/// `OptionSetType::option`. This always fails with an error that says so.
+impl<'de> Deserialize<'de> for OptionSet {
+    fn deserialize<D: Deserializer<'de>>(_deserializer: D) -> Result<Self, D::Error> {
+        Err(de::Error::custom("an option set value cannot be deserialized without its option set; deserialize the option name and look it up with OptionSetType::option"))
+    }
+}
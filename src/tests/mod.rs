@@ -1,4 +1,8 @@
-use crate::{engine::PowerFxEngine, evaluator::Session, models::{DataValue, Record}};
+use std::{future::Future, sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+use chrono::NaiveDate;
+
+use crate::{ast::CommentKind, convert::PowerFxValue, printer::{convert_formula, format_formula, rename_in_formula, FormatOptions}, visitor::{called_functions, referenced_identifiers, rename_identifier, RenameOptions, Visitor}, ast::{BinaryExpression, Comment, Expression, FunctionExpression, Literal, UnaryExpression}, lexer::{parse_formula, DecimalSeparator}, columnar::{Column, ColumnarTable}, function_registry::{AsyncScalarFunction, BoxFuture, FunctionKind, Signature, ValueType}, engine::{EvaluationOptions, PowerFxEngine}, evaluator::{join_result, EvaluationError, Session}, models::{DataValue, Record, Table}, validator::FormulaMode};
 
 
 #[test]
@@ -22,6 +26,302 @@ fn arithmetic() {
     assert_eq!(result, DataValue::Number(1.5));
 }
 
+#[test]
+fn numeric_literals_and_unary_operators() {
+    let engine = PowerFxEngine::new();
+
+    let mut session = Session::new();
+    session.set_variable("a", DataValue::Number(2.0));
+    session.set_variable("b", DataValue::Number(3.0));
+
+    let result = engine.evaluate("a -1", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(1.0));
+
+    let result = engine.evaluate("-(a + b) * -2", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(10.0));
+
+    let result = engine.evaluate("1e6 / 2.5E2 + .5 + 1.25", None).unwrap();
+    assert_eq!(result, DataValue::Number(4001.75));
+
+    let result = engine.evaluate("200 * 15% + 50%", None).unwrap();
+    assert_eq!(result, DataValue::Number(30.5));
+
+    let result = engine.evaluate("Mod(-7, 3) + Mod(7, 3)", None).unwrap();
+    assert_eq!(result, DataValue::Number(3.0));
+
+    let result = engine.evaluate("Mod(\"7\", 3) + Mod(true, \"2\")", None).unwrap();
+    assert_eq!(result, DataValue::Number(2.0));
+
+    let result = engine.evaluate("Abs(\"-3\") + Sqrt(\"4\") + Abs(Blank())", None).unwrap();
+    assert_eq!(result, DataValue::Number(5.0));
+
+    let result = engine.evaluate("Sqrt(\"four\")", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("Mod(7, \"0\")", None);
+    assert!(matches!(result, Err(EvaluationError::DivideByZero)));
+
+    let result = engine.evaluate("7 % 3", None);
+    assert!(matches!(result, Err(EvaluationError::ParseError(_))));
+}
+
+#[test]
+fn comments_and_whitespace() {
+    let engine = PowerFxEngine::new();
+
+    let formula = "// routing rule\nSum(\n\t1,\r\n\t2 /* two */, 3\n) & \"//not a comment\"; /* done */";
+
+    let result = engine.evaluate(formula, None).unwrap();
+    assert_eq!(result, DataValue::Text("6//not a comment".into()));
+
+    let parsed = parse_formula(formula, DecimalSeparator::Dot).unwrap();
+    assert_eq!(parsed.expressions.len(), 1);
+    assert_eq!(&formula[parsed.spans[0].clone()], "Sum(\n\t1,\r\n\t2 /* two */, 3\n) & \"//not a comment\"");
+
+    let comments = |comments: &[Comment]| comments.iter().map(|c| (c.kind, c.text.to_string(), formula[c.span.clone()].to_string())).collect::<Vec<_>>();
+    assert_eq!(comments(&parsed.trivia[0].leading), vec![(CommentKind::Line, " routing rule".into(), "// routing rule".into())]);
+    assert_eq!(comments(&parsed.trivia[0].trailing), vec![(CommentKind::Block, " done ".into(), "/* done */".into())]);
+    assert!(parsed.comments.is_empty());
+
+    let Expression::BinaryExpression(BinaryExpression::Concat(sum, _)) = &parsed.expressions[0] else { panic!("expected a concatenation") };
+    let Expression::FunctionExpression(sum) = sum.as_ref() else { panic!("expected a call") };
+    assert_eq!(comments(&sum.trivia[1].trailing), vec![(CommentKind::Block, " two ".into(), "/* two */".into())]);
+    assert!(sum.trivia[0].is_empty() && sum.trivia[2].is_empty());
+
+    let formatted = format_formula("Sum(1, // one\n 2)", &FormatOptions::default()).unwrap();
+    assert_eq!(formatted, "Sum(\n    1, // one\n    2\n)");
+    assert_eq!(convert_formula("Sum(1 /* one */, /* two */ 2) + /* three */ 3", DecimalSeparator::Dot, DecimalSeparator::Comma).unwrap(), "/* three */ Sum(1; /* one */ /* two */ 2) + 3");
+
+    let result = engine.evaluate("1 + /* unclosed", None);
+    assert!(matches!(result, Err(EvaluationError::ParseError(_))));
+}
+
+#[test]
+fn quoted_and_unicode_names() {
+    let engine = PowerFxEngine::new();
+    engine.register_fn("Präfix", |text: String| format!("Nr. {}", text));
+
+    let mut session = Session::new();
+    session.set_variable("Order Total", DataValue::Number(40.0));
+    session.set_variable("Número", DataValue::Number(2.0));
+    session.set_variable("orders", DataValue::Table(vec![
+        Record::from(vec![("Número de cliente".into(), DataValue::Number(7.0)), ("Customer's Name".into(), DataValue::Text("Ana".into()))]),
+        Record::from(vec![("Número de cliente".into(), DataValue::Number(9.0)), ("Customer's Name".into(), DataValue::Text("Bo".into()))]),
+    ].into()));
+
+    let result = engine.evaluate("'Order Total' * Número", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(80.0));
+
+    let result = engine.evaluate("First(Filter(orders, 'Número de cliente' > 8)).'Customer''s Name'", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("Bo".into()));
+
+    let result = engine.evaluate("{ 'Line Total': 5 }.'Line Total' + Sum(orders, 'Número de cliente')", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(21.0));
+
+    let result = engine.evaluate("Präfix(\"say \"\"hi\"\"\")", None).unwrap();
+    assert_eq!(result, DataValue::Text("Nr. say \"hi\"".into()));
+
+    session.set_variable("inventory", DataValue::Text("abc".into()));
+    session.set_variable("trueValue", DataValue::Boolean(false));
+    let result = engine.evaluate("\"a\" in inventory And Not trueValue", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    for formula in ["\"a\" ininventory", "\"a\" exactininventory", "trueValue Andx"] {
+        let result = engine.evaluate(formula, Some(&mut session));
+        assert!(matches!(result, Err(EvaluationError::ParseError(_))), "{formula}");
+    }
+
+    let result = engine.evaluate("'Hello'", Some(&mut session));
+    assert!(result.is_err());
+}
+
+#[test]
+fn decimal_comma_locale() {
+    let engine = PowerFxEngine::new();
+    let options = EvaluationOptions { decimal_separator: DecimalSeparator::Comma, ..Default::default() };
+
+    let mut session = Session::new();
+    let result = engine.evaluate_with_options("Set(rate; 0,25);; Sum(Table({ a: 1,5; b: 2 }; { a: 2,5; b: 3 }); a) * rate", Some(&mut session), &options).unwrap();
+    assert_eq!(result, DataValue::Number(1.0));
+
+    let formula = "// discount\nIf(total > 1000, total * 0.9, total - 2.5); Text(-(1 + 2) * 3)";
+    let converted = convert_formula(formula, DecimalSeparator::Dot, DecimalSeparator::Comma).unwrap();
+    assert_eq!(converted, "// discount\nIf(total > 1000; total * 0,9; total - 2,5);; Text(-(1 + 2) * 3)");
+
+    let restored = convert_formula(&converted, DecimalSeparator::Comma, DecimalSeparator::Dot).unwrap();
+    assert_eq!(restored, "// discount\nIf(total > 1000, total * 0.9, total - 2.5); Text(-(1 + 2) * 3)");
+
+    let result = engine.evaluate_with_options("Sum(1, 5)", None, &options);
+    assert!(matches!(result, Err(EvaluationError::ParseError(_))));
+}
+
+#[test]
+fn printing_and_formatting() {
+    let parsed = parse_formula("((1 + 2)) * -(3 - (4 - 5)) && !('Order Total' >= 50%) || x.'Line Items'", DecimalSeparator::Dot).unwrap();
+    assert_eq!(parsed.expressions[0].to_string(), "(1 + 2) * -(3 - (4 - 5)) And Not 'Order Total' >= 50% Or x.'Line Items'");
+
+    let formula = "Set(total,Sum(Filter(orders,Status=\"Open\"),Amount)); // notify\nIf(total>1000,Notify(\"Large order: \"&Text(total),\"Warning\"),Notify(\"OK\"))";
+    let formatted = format_formula(formula, &FormatOptions { max_width: 50, ..Default::default() }).unwrap();
+    assert_eq!(formatted, [
+        "Set(",
+        "    total,",
+        "    Sum(Filter(orders, Status = \"Open\"), Amount)",
+        "); // notify",
+        "If(",
+        "    total > 1000,",
+        "    Notify(",
+        "        \"Large order: \" & Text(total),",
+        "        \"Warning\"",
+        "    ),",
+        "    Notify(\"OK\")",
+        ")",
+    ].join("\n"));
+
+    assert_eq!(format_formula(&formatted, &FormatOptions { max_width: 50, ..Default::default() }).unwrap(), formatted);
+
+    let formula = "If(Not(a), And(b, Or(c, d, 'Not'), Not a), Or(x > 1 Or y, true) And 'true'.'And')";
+    let parsed = parse_formula(formula, DecimalSeparator::Dot).unwrap();
+    for options in [FormatOptions::default(), FormatOptions { max_width: 20, ..Default::default() }] {
+        let formatted = format_formula(formula, &options).unwrap();
+        assert_eq!(parse_formula(&formatted, DecimalSeparator::Dot).unwrap().expressions, parsed.expressions, "{formatted}");
+    }
+    assert_eq!(parsed.expressions[0].to_string(), "If(Not(a), And(b, Or(c, d, 'Not'), Not a), Or(x > 1 Or y, true) And 'true'.'And')");
+    assert_eq!(convert_formula("And(a, 1.5)", DecimalSeparator::Dot, DecimalSeparator::Comma).unwrap(), "And(a; 1,5)");
+
+    let date = UnaryExpression::literal(Literal::Date(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()));
+    let engine = PowerFxEngine::new();
+    assert_eq!(engine.evaluate(&date.to_string(), None).unwrap(), DataValue::Date(NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()));
+    assert!(matches!(engine.evaluate("Date(2023, 2, 29)", None), Err(EvaluationError::InvalidArgument(_))));
+}
+
+#[test]
+fn visitors_and_renaming() {
+    let formula = parse_formula("If(Sum(Filter(orders, ThisRecord.Price > 10), Price * {q: Qty}.q) > limit, Notify(\"Big\"))", DecimalSeparator::Dot).unwrap();
+    let expression = &formula.expressions[0];
+
+    let identifiers: Vec<_> = referenced_identifiers(expression).into_iter().map(|name| name.to_string()).collect();
+    assert_eq!(identifiers, ["Price", "Qty", "limit", "orders"]);
+
+    let functions: Vec<_> = called_functions(expression).into_iter().map(|name| name.to_string()).collect();
+    assert_eq!(functions, ["Filter", "If", "Notify", "Sum"]);
+
+    struct CallDepth { depth: usize, max: usize }
+    impl Visitor for CallDepth {
+        fn visit_function_expression(&mut self, func: &FunctionExpression) {
+            self.depth += 1;
+            self.max = self.max.max(self.depth);
+            crate::walk_function_expression(self, func);
+            self.depth -= 1;
+        }
+    }
+    let mut depth = CallDepth { depth: 0, max: 0 };
+    depth.visit_expression(expression);
+    assert_eq!(depth.max, 3);
+
+    let mut renamed: Expression = expression.clone();
+    assert_eq!(rename_identifier(&mut renamed, "Price", "UnitPrice", &RenameOptions::default()), 2);
+    assert_eq!(renamed.to_string(), "If(Sum(Filter(orders, ThisRecord.UnitPrice > 10), UnitPrice * { q: Qty }.q) > limit, Notify(\"Big\"))");
+
+    let renamed = rename_in_formula("Set(t, First(orders).Price); /* total */ t + Price", "Price", "Unit Price", DecimalSeparator::Dot, &RenameOptions::default()).unwrap();
+    assert_eq!(renamed, "Set(t, First(orders).'Unit Price'); /* total */ t + 'Unit Price'");
+
+    let engine = PowerFxEngine::new();
+    engine.register_option_set("Status", vec![("Price", 1, "Price"), ("Free", 2, "Free")]);
+    let rename = |formula: &str| rename_in_formula(formula, "Price", "UnitPrice", DecimalSeparator::Dot, &engine.rename_options()).unwrap();
+    assert_eq!(rename("With({ Price: Price }, Price * 2) + price"), "With({ Price: UnitPrice }, Price * 2) + UnitPrice");
+    assert_eq!(rename("With({ Price: 5 }, ThisRecord.Price + Parent.Price + order.Price)"), "With({ Price: 5 }, ThisRecord.Price + Parent.UnitPrice + order.UnitPrice)");
+    assert_eq!(rename("If(Status.Price = item.Status, { Price: 1 }.Price, PRICE)"), "If(Status.Price = item.Status, { Price: 1 }.Price, UnitPrice)");
+
+    engine.set_strict_names(true);
+    assert_eq!(rename("price + Price"), "price + UnitPrice");
+}
+
+#[test]
+fn coercion() {
+    let engine = PowerFxEngine::new();
+
+    let result = engine.evaluate("\"5\" * 2 + true - Blank()", None).unwrap();
+    assert_eq!(result, DataValue::Number(11.0));
+
+    let result = engine.evaluate("\"10\" > 9", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let result = engine.evaluate("\"Total: \" & 3 + 4 & Blank() & \" \" & true", None).unwrap();
+    assert_eq!(result, DataValue::Text("Total: 7 true".into()));
+
+    let result = engine.evaluate("JSON(Table({ a: 1 }), JSONFormat.Compact & JSONFormat.IgnoreBinaryData)", None).unwrap();
+    assert_eq!(result, DataValue::Text("[{\"a\":1}]".into()));
+
+    let result = engine.evaluate("5 - \"abc\"", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("\"5\" = 5", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("Table({ a: 1 }) & \"x\"", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    for formula in ["1 && true", "!\"x\"", "false || 0", "And(1, true)"] {
+        let result = engine.evaluate(formula, None);
+        assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))), "{formula}");
+    }
+
+    let result = engine.evaluate("!Blank() && (Blank() || true)", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+}
+
+#[test]
+fn blank_semantics() {
+    let engine = PowerFxEngine::new();
+
+    let result = engine.evaluate("Blank() + 1", None).unwrap();
+    assert_eq!(result, DataValue::Number(1.0));
+
+    let result = engine.evaluate("Blank() = Blank() And Blank() <> 0 And Blank() < 5", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let result = engine.evaluate("If(Blank(), \"yes\", \"no\") & Not(Blank())", None).unwrap();
+    assert_eq!(result, DataValue::Text("notrue".into()));
+
+    let result = engine.evaluate("1 / Blank()", None);
+    assert!(matches!(result, Err(EvaluationError::DivideByZero)));
+
+    let result = engine.evaluate("Sum(1, Blank(), 2) + Average(Blank(), 4, 6)", None).unwrap();
+    assert_eq!(result, DataValue::Number(8.0));
+
+    let result = engine.evaluate("Sum(\"7\", 3, true) + Max(Table({ a: \"12\" }, { a: 4 }), a)", None).unwrap();
+    assert_eq!(result, DataValue::Number(23.0));
+
+    let result = engine.evaluate("Sum(\"abc\", 3)", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("Average(Filter(Table({ a: 1 }), a > 1), a)", None).unwrap();
+    assert_eq!(result, DataValue::Blank);
+
+    let table = ColumnarTable::new(vec![
+        ("Age".into(), Column::Number(vec![Some(30.0), None, Some(40.0)])),
+    ]).unwrap();
+    let mut session = Session::new();
+    session.set_variable("people", DataValue::Table(table.into()));
+    session.set_variable("rows", DataValue::Table(vec![
+        Record::from(vec![("Age".into(), DataValue::Number(30.0))]),
+        Record::from(vec![("Age".into(), DataValue::Blank)]),
+        Record::from(vec![("Age".into(), DataValue::Number(40.0))]),
+    ].into()));
+
+    let result = engine.evaluate("Average(people, Age) + Min(people, Age)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(65.0));
+
+    let result = engine.evaluate("Average(rows, Age) + Min(rows, Age)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(65.0));
+
+    let result = engine.evaluate("If(IsEmpty(Filter(rows, Blank())), Sum(Filter(rows, If(Age > 35, true)), Age))", Some(&mut session));
+    assert_eq!(result.unwrap(), DataValue::Number(40.0));
+
+    let result = engine.evaluate("Filter(rows, Age)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+}
+
 #[test]
 fn aggregation() {
     let engine = PowerFxEngine::new();
@@ -49,7 +349,7 @@ fn aggregation_with_tables() {
     let engine = PowerFxEngine::new();
     
     let mut session = Session::new();
-    _ = engine.evaluate("Set(table1, Table({ Name: 'Foo', Age: 30 }, { Name: 'Bar', Age: 43 }))", Some(&mut session));
+    _ = engine.evaluate("Set(table1, Table({ Name: \"Foo\", Age: 30 }, { Name: \"Bar\", Age: 43 }))", Some(&mut session));
 
     let result = engine.evaluate("Min(table1, Age)", Some(&mut session)).unwrap();
     assert_eq!(result, DataValue::Number(30.0));
@@ -73,7 +373,7 @@ fn tables() {
     let engine = PowerFxEngine::new();
     
     let mut session = Session::new();
-    _ = engine.evaluate("Set(table1, Table({ Name: 'Foo', Age: 30 }, { Name: 'Baz', Age: 25 }, { Name: 'Bar', Age: 43 }))", Some(&mut session));
+    _ = engine.evaluate("Set(table1, Table({ Name: \"Foo\", Age: 30 }, { Name: \"Baz\", Age: 25 }, { Name: \"Bar\", Age: 43 }))", Some(&mut session));
 
     let result = engine.evaluate("Filter(table1, Age >= 29)", Some(&mut session)).unwrap();
     assert_eq!(result, DataValue::Table(vec![
@@ -85,13 +385,699 @@ fn tables() {
             ("Name".into(), DataValue::Text("Bar".into())),
             ("Age".into(), DataValue::Number(43.0)),
         ]),
-    ]));
+    ].into()));
 
     let result = engine.evaluate("First(table1)", Some(&mut session)).unwrap();
     assert_eq!(result, DataValue::Record(Record::from(vec![
         ("Name".into(), DataValue::Text("Foo".into())),
         ("Age".into(), DataValue::Number(30.0)),
     ])));
+
+    let result = engine.evaluate("Index(table1, 1).Name & Index(table1, 3).Name", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("FooBar".into()));
+
+    for formula in ["Index(table1, 0)", "Index(table1, 4)", "Index(table1, 1.5)", "Index(table1, -1)"] {
+        let result = engine.evaluate(formula, Some(&mut session));
+        assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))), "{formula}");
+    }
+}
+
+
+
+
+#[test]
+fn data_formulas_reject_behavior_functions() {
+    let engine = PowerFxEngine::new();
+    let options = EvaluationOptions { mode: FormulaMode::Data, ..Default::default() };
+
+    let mut session = Session::new();
+    _ = engine.evaluate("Set(table1, Table({ Name: \"Foo\", Age: 30 }, { Name: \"Bar\", Age: 43 }))", Some(&mut session));
+
+    let result = engine.evaluate_with_options("Set(a, 1)", Some(&mut session), &options);
+    assert!(matches!(result, Err(EvaluationError::BehaviorFunctionNotAllowed(name)) if name == "Set"));
+
+    let result = engine.evaluate_with_options("Filter(table1, Set(a, Age) = Blank)", Some(&mut session), &options);
+    assert!(matches!(result, Err(EvaluationError::BehaviorFunctionNotAllowed(_))));
+    assert!(session.get_variable("a").is_none());
+
+    let result = engine.evaluate_with_options("Sum(table1, Age)", Some(&mut session), &options).unwrap();
+    assert_eq!(result, DataValue::Number(73.0));
+}
+
+#[test]
+fn data_formulas_reject_chaining() {
+    let engine = PowerFxEngine::new();
+    let options = EvaluationOptions { mode: FormulaMode::Data, ..Default::default() };
+
+    let result = engine.validate("1 + 2; 3 + 4", &options);
+    assert!(matches!(result, Err(EvaluationError::ChainingNotAllowed)));
+
+    let mut session = Session::new();
+    let result = engine.evaluate("Set(a, 2); a * 3", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(6.0));
+}
+
+#[test]
+fn name_resolution() {
+    let engine = PowerFxEngine::new();
+
+    let mut session = Session::new();
+    session.set_variable("total", DataValue::Number(2.0));
+
+    let result = engine.evaluate("sUM(Table({ a: 1 }, { a: 2 }), a) + total", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(5.0));
+
+    let result = engine.evaluate("Summ(Table({ a: 1 }), a)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::UnknownFunction { did_you_mean: Some(name), .. }) if name == "Sum"));
+
+    let result = engine.evaluate("totl * 2", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::UnknownIdentifier { did_you_mean: Some(name), .. }) if name == "total"));
+
+    let result = engine.evaluate("Frobnicate(1)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::UnknownFunction { did_you_mean: None, .. })));
+
+    engine.set_strict_names(true);
+    let result = engine.evaluate("sum(Table({ a: 1 }), a)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::UnknownFunction { did_you_mean: Some(name), .. }) if name == "Sum"));
+}
+
+#[test]
+fn function_metadata() {
+    let engine = PowerFxEngine::new();
+
+    let functions = engine.list_functions();
+    assert!(functions.windows(2).all(|pair| pair[0].name < pair[1].name));
+
+    let set = functions.iter().find(|f| f.name == "Set").unwrap();
+    assert_eq!(set.signature.kind, FunctionKind::Behavior);
+    assert_eq!(set.signature.parameters.len(), 2);
+
+    let mid = functions.iter().find(|f| f.name == "Mid").unwrap();
+    assert_eq!((mid.signature.min_arity, mid.signature.max_arity), (2, Some(3)));
+    assert_eq!(mid.signature.return_type, ValueType::Text);
+
+    let result = engine.evaluate("Mid(\"abc\")", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgumentCount(message)) if message == "Mid expects 2 to 3 arguments, found 1"));
+
+    let result = engine.evaluate("Not(true, false)", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgumentCount(message)) if message == "Not expects 1 argument, found 2"));
+
+    let result = engine.evaluate("Filter(Table({ a: 1 }))", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgumentCount(message)) if message == "Filter expects at least 2 arguments, found 1"));
+}
+
+#[test]
+fn closure_functions() {
+    let engine = PowerFxEngine::new();
+
+    engine.register_fn("Discount", |price: f64, pct: f64| price * (1.0 - pct / 100.0));
+    let result = engine.evaluate("Discount(200, 25)", None).unwrap();
+    assert_eq!(result, DataValue::Number(150.0));
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    engine.register_fn("Greet", move |name: String| format!("Hello {}", name));
+    engine.register_fn("NextId", move || counter.fetch_add(1, Ordering::Relaxed) as f64 + 1.0);
+    engine.evaluate("NextId(); NextId()", None).unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    let result = engine.evaluate("Greet(\"World\")", None).unwrap();
+    assert_eq!(result, DataValue::Text("Hello World".into()));
+
+    let result = engine.evaluate("Greet(1)", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(message)) if message.starts_with("Argument 1 of Greet: Expected text")));
+
+    let result = engine.evaluate("Greet(\"a\", \"b\")", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgumentCount(_))));
+
+    engine.register_fn("SafeDivide", |a: f64, b: f64| if b == 0.0 { Err(EvaluationError::DivideByZero) } else { Ok(a / b) });
+    let result = engine.evaluate("SafeDivide(1, 0)", None);
+    assert!(matches!(result, Err(EvaluationError::DivideByZero)));
+
+    engine.register_fn("Repeat", |text: String, count: u8| text.repeat(count as usize));
+    let result = engine.evaluate("Repeat(\"ab\", 3)", None).unwrap();
+    assert_eq!(result, DataValue::Text("ababab".into()));
+
+    engine.register_fn("Pad", |text: String, width: Option<u8>, fill: Option<String>| {
+        let fill = fill.unwrap_or_else(|| " ".into());
+        format!("{}{}", fill.repeat((width.unwrap_or(4) as usize).saturating_sub(text.len())), text)
+    });
+    assert_eq!(engine.evaluate("Pad(\"ab\")", None).unwrap(), DataValue::Text("  ab".into()));
+    assert_eq!(engine.evaluate("Pad(\"ab\", 3)", None).unwrap(), DataValue::Text(" ab".into()));
+    assert_eq!(engine.evaluate("Pad(\"ab\", Blank(), \"0\")", None).unwrap(), DataValue::Text("00ab".into()));
+    assert!(matches!(engine.evaluate("Pad()", None), Err(EvaluationError::InvalidArgumentCount(_))));
+
+    engine.register_fn("Echo", |value: DataValue| value);
+    assert_eq!(engine.evaluate("Echo({a: 1}).a", None).unwrap(), DataValue::Number(1.0));
+
+    for count in ["2.7", "-1", "300", "Sqrt(-1)"] {
+        let result = engine.evaluate(&format!("Repeat(\"ab\", {})", count), None);
+        assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))), "{}", count);
+    }
+    assert!(matches!(i64::from_data_value(DataValue::Number(9223372036854775808.0)), Err(EvaluationError::InvalidArgument(_))));
+    assert_eq!(u64::from_data_value(DataValue::Number(18446744073709549568.0)).unwrap(), 18446744073709549568);
+
+    let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let writer = log.clone();
+    engine.register_fn_with("Log", "Writes a message to the log.", FunctionKind::Behavior, move |message: String| {
+        writer.lock().unwrap().push(message);
+        true
+    });
+    let metadata = engine.list_functions().into_iter().find(|f| f.name == "Log").unwrap();
+    assert_eq!(metadata.signature.description, "Writes a message to the log.");
+    assert_eq!(metadata.signature.kind, FunctionKind::Behavior);
+
+    let options = EvaluationOptions { mode: FormulaMode::Data, ..Default::default() };
+    let result = engine.evaluate_with_options("Log(\"hi\")", None, &options);
+    assert!(matches!(result, Err(EvaluationError::BehaviorFunctionNotAllowed(_))));
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn namespaces_and_enums() {
+    let engine = PowerFxEngine::new();
+
+    engine.register_fn("Math.Round", |n: f64| n.round());
+    engine.register_enum("Color", vec![
+        ("Red", DataValue::Text("#FF0000".into())),
+        ("Blue", DataValue::Text("#0000FF".into())),
+    ]);
+
+    let result = engine.evaluate("Math.Round(7 / 2) + math.round(1)", None).unwrap();
+    assert_eq!(result, DataValue::Number(5.0));
+
+    let round = engine.list_functions().into_iter().find(|f| f.name == "Math.Round").unwrap();
+    assert_eq!(round.namespace(), Some("Math"));
+
+    let result = engine.evaluate("Color.Red", None).unwrap();
+    let red = match result {
+        DataValue::Enum(e) => e,
+        v => panic!("Expected an enum value, found {:?}", v),
+    };
+    assert_eq!((red.enum_name.as_ref(), red.name.as_ref()), ("Color", "Red"));
+    assert_eq!(red.value.as_ref(), &DataValue::Text("#FF0000".into()));
+
+    let result = engine.evaluate("Color.Red = Color.Red And Color.Red <> Color.Blue", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let result = engine.evaluate("Text(Color.Blue)", None).unwrap();
+    assert_eq!(result, DataValue::Text("#0000FF".into()));
+
+    let result = engine.evaluate("Color.Rde", None);
+    assert!(matches!(result, Err(EvaluationError::UnknownIdentifier { did_you_mean: Some(name), .. }) if name == "Color.Red"));
+
+    let mut session = Session::new();
+    session.set_variable("Color", DataValue::Record(Record::from(vec![("Red".into(), DataValue::Number(1.0))])));
+    let result = engine.evaluate("Color.Red", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(1.0));
+}
+
+#[test]
+fn option_sets() {
+    let engine = PowerFxEngine::new();
+
+    engine.register_option_set("Status", vec![("Active", 1, "Active"), ("OnHold", 2, "On Hold")]);
+
+    let result = engine.evaluate("Status.OnHold = Status.OnHold And Status.OnHold <> Status.Active", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let mut session = Session::new();
+    session.set_variable("status", engine.evaluate("Status.OnHold", None).unwrap());
+    let result = engine.evaluate("If(status = Status.OnHold, Text(status), \"other\")", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("On Hold".into()));
+
+    let result = engine.evaluate("Value(Status.Active)", None).unwrap();
+    assert_eq!(result, DataValue::Number(1.0));
+
+    let result = engine.evaluate("JSON(Status.Active)", None).unwrap();
+    assert_eq!(result, DataValue::Text("\"Active\"".into()));
+
+    let result = engine.evaluate("Status.Actve", None);
+    assert!(matches!(result, Err(EvaluationError::UnknownIdentifier { did_you_mean: Some(name), .. }) if name == "Status.Active"));
+}
+
+#[test]
+fn logical_functions() {
+    let engine = PowerFxEngine::new();
+    engine.register_option_set("Status", vec![("Active", 1, "Active"), ("OnHold", 2, "On Hold")]);
+
+    let mut session = Session::new();
+    session.set_variable("status", engine.evaluate("Status.OnHold", None).unwrap());
+    session.set_variable("amount", DataValue::Number(250.0));
+
+    let result = engine.evaluate("Switch(status, Status.Active, \"sales\", Status.OnHold, \"support\", \"triage\")", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("support".into()));
+
+    let result = engine.evaluate("Switch(3, 1, \"one\", 2, \"two\")", None).unwrap();
+    assert_eq!(result, DataValue::Blank);
+
+    let result = engine.evaluate("If(amount > 1000, \"large\", amount > 100, \"medium\", \"small\")", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("medium".into()));
+
+    let result = engine.evaluate("If(amount > 1000, \"large\", amount > 500, \"medium\")", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Blank);
+
+    let result = engine.evaluate("Coalesce(Blank(), \"\", \"fallback\", \"other\")", None).unwrap();
+    assert_eq!(result, DataValue::Text("fallback".into()));
+
+    let result = engine.evaluate("IsBlank(Blank()) And IsBlank(\"\") And Not(IsBlank(0))", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let result = engine.evaluate("IsEmpty(Filter(Table({ a: 1 }), a > 1)) And Not(IsEmpty(Table({ a: 1 })))", None).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let result = engine.evaluate("Switch(1, 1)", None);
+    assert!(matches!(result, Err(EvaluationError::InvalidArgumentCount(_))));
+}
+
+#[test]
+fn layered_functions() {
+    let engine = PowerFxEngine::new();
+
+    let tenant = engine.overlay();
+    tenant.register_fn("TaxRate", || 20.0);
+    tenant.register_fn("Upper", |text: String| format!("{}!", text.to_uppercase()));
+
+    let result = tenant.evaluate("Sum(Filter(Table({ a: 10 }, { a: 30 }), a > TaxRate()), a)", None).unwrap();
+    assert_eq!(result, DataValue::Number(30.0));
+
+    let result = tenant.evaluate("Upper(\"abc\")", None).unwrap();
+    assert_eq!(result, DataValue::Text("ABC!".into()));
+
+    let result = engine.evaluate("Upper(\"abc\")", None).unwrap();
+    assert_eq!(result, DataValue::Text("ABC".into()));
+
+    let result = engine.evaluate("TaxRate()", None);
+    assert!(matches!(result, Err(EvaluationError::UnknownFunction { .. })));
+
+    let restricted = tenant.overlay();
+    restricted.hide_function("Set");
+    let result = restricted.evaluate("Set(a, TaxRate())", None);
+    assert!(matches!(result, Err(EvaluationError::UnknownFunction { .. })));
+    assert!(restricted.list_functions().iter().all(|f| f.name != "Set"));
+    assert!(tenant.evaluate("Set(a, TaxRate())", None).is_ok());
+
+    assert!(tenant.unregister_function("Upper"));
+    assert!(!tenant.unregister_function("Upper"));
+    let result = tenant.evaluate("Upper(\"abc\")", None).unwrap();
+    assert_eq!(result, DataValue::Text("ABC".into()));
+
+    tenant.register_fn("upper", |text: String| text.to_lowercase());
+    let uppers: Vec<_> = tenant.list_functions().into_iter().filter(|f| f.name.eq_ignore_ascii_case("upper")).collect();
+    assert_eq!(uppers.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["upper"]);
+    assert_eq!(tenant.evaluate("UPPER(\"ABC\")", None).unwrap(), DataValue::Text("abc".into()));
+    assert!(tenant.unregister_function("upper"));
+    assert_eq!(tenant.evaluate("UPPER(\"abc\")", None).unwrap(), DataValue::Text("ABC".into()));
+
+    tenant.register_fn("Twice", |n: f64| n * 2.0);
+    tenant.register_fn("twice", |n: f64| n * 3.0);
+    assert_eq!(tenant.evaluate("Twice(1)", None).unwrap(), DataValue::Number(3.0));
+    assert!(!tenant.unregister_function("Twice"));
+    assert!(tenant.unregister_function("twice"));
+    assert!(matches!(tenant.evaluate("TWICE(1)", None), Err(EvaluationError::UnknownFunction { .. })));
+
+    tenant.set_strict_names(true);
+    tenant.register_fn("Half", |n: f64| n / 2.0);
+    tenant.register_fn("half", |n: f64| n / 4.0);
+    assert_eq!(tenant.list_functions().iter().filter(|f| f.name.eq_ignore_ascii_case("half")).count(), 2);
+    assert!(tenant.unregister_function("half"));
+    tenant.set_strict_names(false);
+    assert_eq!(tenant.evaluate("HALF(4)", None).unwrap(), DataValue::Number(2.0));
+}
+
+/// Stands in for a database lookup that takes a while to answer. It records how many calls are in flight at once,
+/// and waits for `gate` to be opened, if there is one.
+#[derive(Default)]
+struct SlowLookup {
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    gate: Option<tokio::sync::Semaphore>,
+}
+
+impl AsyncScalarFunction for SlowLookup {
+    fn call(&self, args: Vec<DataValue>) -> BoxFuture<'_, Result<DataValue, EvaluationError>> {
+        Box::pin(async move {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            match &self.gate {
+                Some(gate) => drop(gate.acquire().await),
+                None => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            match args.first() {
+                Some(DataValue::Number(id)) => Ok(DataValue::Number(id * 10.0)),
+                _ => Err(EvaluationError::InvalidArgument("Expected an id".to_string())),
+            }
+        })
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::new("Looks up a value by id.").param("id", ValueType::Number).returns(ValueType::Number)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_functions() {
+    let engine = PowerFxEngine::new();
+    let lookup = Arc::new(SlowLookup::default());
+    engine.register_async_function("Lookup", lookup.clone());
+
+    let mut session = Session::new();
+    let result = engine.evaluate_async("Set(x, Lookup(4)); x + 1", Some(&mut session)).await.unwrap();
+    assert_eq!(result, DataValue::Number(41.0));
+    assert_eq!(session.get_variable("x"), Some(&DataValue::Number(40.0)));
+    assert_eq!(lookup.max_in_flight.load(Ordering::SeqCst), 1);
+
+    let ids = (1..=40).map(|id| Record::from(vec![("Id".into(), DataValue::Number(id as f64))])).collect::<Vec<_>>();
+    session.set_variable("ids", DataValue::Table(ids.into()));
+
+    let result = engine.evaluate_async("Sum(ForAll(ids, Lookup(Id)), Value)", Some(&mut session)).await.unwrap();
+    assert_eq!(result, DataValue::Number(8200.0));
+    let max_in_flight = lookup.max_in_flight.load(Ordering::SeqCst);
+    assert!(max_in_flight > 1 && max_in_flight <= 16, "{} calls were in flight at once", max_in_flight);
+
+    let result = engine.evaluate("Last(ForAll(ids, { Double: Id * 2 }))", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Record(Record::from(vec![("Double".into(), DataValue::Number(80.0))])));
+
+    let result = engine.evaluate("Lookup(1)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::AsyncEvaluationRequired(name)) if name == "Lookup"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_async_evaluation_keeps_session() {
+    let engine = PowerFxEngine::new();
+    let lookup = Arc::new(SlowLookup { gate: Some(tokio::sync::Semaphore::new(0)), ..Default::default() });
+    engine.register_async_function("Lookup", lookup.clone());
+
+    let mut session = Session::new();
+    session.set_variable("x", DataValue::Number(1.0));
+
+    // The lookup cannot finish until the gate is opened, so the evaluation is always dropped first.
+    let result = tokio::time::timeout(Duration::from_millis(10), engine.evaluate_async("Set(y, 2); Set(x, Lookup(4))", Some(&mut session))).await;
+    assert!(result.is_err());
+    assert_eq!(session.get_variable("x"), Some(&DataValue::Number(1.0)));
+    assert_eq!(session.get_variable("y"), None);
+
+    lookup.gate.as_ref().unwrap().add_permits(1);
+}
+
+#[test]
+fn async_evaluation_requires_runtime() {
+    let engine = PowerFxEngine::new();
+    let mut future = std::pin::pin!(engine.evaluate_async("1 + 1", None));
+    let result = future.as_mut().poll(&mut std::task::Context::from_waker(std::task::Waker::noop()));
+    assert!(matches!(result, std::task::Poll::Ready(Err(EvaluationError::AsyncRuntimeRequired))));
+}
+
+#[test]
+fn cancelled_evaluation_tasks() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let task = tokio::spawn(std::future::pending::<()>());
+        task.abort();
+        assert!(matches!(join_result(task.await), Err(EvaluationError::Cancelled)));
+
+        let task = tokio::spawn(async { panic!("host function failed") });
+        let joined = task.await;
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| join_result(joined))).unwrap_err();
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"host function failed"));
+    });
+}
+
+#[test]
+fn with_scopes() {
+    let engine = PowerFxEngine::new();
+    
+    let mut session = Session::new();
+    session.set_variable("x", DataValue::Number(1.0));
+
+    let result = engine.evaluate("With({ x: 10, y: 5 }, x * y)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(50.0));
+
+    let result = engine.evaluate("With({ x: 10 }, With({ x: 20 }, x) + x)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(30.0));
+    assert_eq!(session.get_variable("x"), Some(&DataValue::Number(1.0)));
+
+    _ = engine.evaluate("Set(table1, Table({ Name: \"Foo\", Age: 30 }, { Name: \"Bar\", Age: 43 }))", Some(&mut session));
+    let result = engine.evaluate("With({ Age: 100, Limit: 40 }, Sum(Filter(table1, Age > Limit), Age))", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(43.0));
+
+    let result = engine.evaluate("With({ y: 1 }, y); y", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::UnknownIdentifier { .. })));
+
+    let result = engine.evaluate("With({ Age: 100 }, Sum(Filter(table1, ThisRecord.Age < Parent.Age), ThisItem.Age))", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(73.0));
+
+    let result = engine.evaluate("With({ Limit: 40 }, Filter(table1, ThisRecord.Limit > 1))", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::UnknownIdentifier { name, .. }) if name == "Limit"));
+
+    let result = engine.evaluate("ThisRecord.x + Parent.x", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("With({ x: 2 }, Self.x)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+}
+
+#[test]
+fn context_variables() {
+    let engine = PowerFxEngine::new();
+    
+    let mut session = Session::new();
+    let result = engine.evaluate("Set(x, 1); UpdateContext({ x: 2, y: 3 }); x + y", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(5.0));
+    assert_eq!(session.get_context_variable("x"), Some(&DataValue::Number(2.0)));
+    assert_eq!(session.clone_variables().get("x"), Some(&DataValue::Number(1.0)));
+
+    let result = engine.evaluate("With({ x: 10 }, x)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(10.0));
+
+    let options = EvaluationOptions { mode: FormulaMode::Data, ..Default::default() };
+    let result = engine.evaluate_with_options("UpdateContext({ x: 4 })", Some(&mut session), &options);
+    assert!(matches!(result, Err(EvaluationError::BehaviorFunctionNotAllowed(_))));
+}
+
+#[test]
+fn shared_table_storage() {
+    let engine = PowerFxEngine::new();
+    
+    let mut session = Session::new();
+    _ = engine.evaluate("Set(table1, Table({ Name: \"Foo\", Age: 30 }, { Name: \"Bar\", Age: 43 }))", Some(&mut session));
+
+    let stored = match session.get_variable("table1") {
+        Some(DataValue::Table(t)) => t.first().unwrap(),
+        _ => panic!("Expected a table"),
+    };
+
+    let mut first = match engine.evaluate("First(table1)", Some(&mut session)).unwrap() {
+        DataValue::Record(r) => r,
+        _ => panic!("Expected a record"),
+    };
+    assert!(Arc::ptr_eq(&first.fields, &stored.fields));
+
+    first.set("Age", DataValue::Number(31.0));
+    assert!(!Arc::ptr_eq(&first.fields, &stored.fields));
+    assert_eq!(stored.get("Age"), Some(&DataValue::Number(30.0)));
+    assert_eq!(first.get("Age"), Some(&DataValue::Number(31.0)));
+}
+
+#[test]
+fn columnar_tables() {
+    let engine = PowerFxEngine::new();
+
+    let table = ColumnarTable::new(vec![
+        ("Name".into(), Column::Text(vec![Some("Foo".into()), Some("Baz".into()), Some("Bar".into())])),
+        ("Age".into(), Column::Number(vec![Some(30.0), Some(25.0), Some(43.0)])),
+    ]).unwrap();
+    
+    let mut session = Session::new();
+    session.set_variable("table1", DataValue::Table(table.into()));
+
+    let result = engine.evaluate("Sum(table1, Age)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(98.0));
+
+    let result = engine.evaluate("Max(table1, Age)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(43.0));
+
+    let result = engine.evaluate("Average(table1, Age * 3)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(98.0));
+
+    let result = engine.evaluate("Filter(table1, Age >= 29)", Some(&mut session)).unwrap();
+    let filtered = match &result {
+        DataValue::Table(t) => t.clone(),
+        _ => panic!("Expected a table"),
+    };
+    assert!(filtered.as_columnar().is_some());
+    assert_eq!(filtered, Table::from(vec![
+        Record::from(vec![
+            ("Name".into(), DataValue::Text("Foo".into())),
+            ("Age".into(), DataValue::Number(30.0)),
+        ]),
+        Record::from(vec![
+            ("Name".into(), DataValue::Text("Bar".into())),
+            ("Age".into(), DataValue::Number(43.0)),
+        ]),
+    ]));
+
+    let result = engine.evaluate("Last(table1)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Record(Record::from(vec![
+        ("Name".into(), DataValue::Text("Bar".into())),
+        ("Age".into(), DataValue::Number(43.0)),
+    ])));
+
+    let mismatched = ColumnarTable::new(vec![
+        ("Name".into(), Column::Text(vec![Some("Foo".into())])),
+        ("Age".into(), Column::Number(vec![])),
+    ]);
+    assert!(matches!(mismatched, Err(EvaluationError::InvalidArgument(_))));
 }
 
+#[test]
+fn parse_json() {
+    let engine = PowerFxEngine::new();
+
+    let mut session = Session::new();
+    session.set_variable("payload", DataValue::Text(r#"{"order": {"id": "A-7", "total": 42, "paid": true, "note": null}, "lines": [{"sku": "X"}, {"sku": "Y"}]}"#.into()));
+    engine.evaluate("Set(body, ParseJSON(payload))", Some(&mut session)).unwrap();
+
+    let result = engine.evaluate("Value(body.order.total) * 2", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Number(84.0));
+
+    let result = engine.evaluate("Text(body.order.id)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("A-7".into()));
+
+    let result = engine.evaluate("Boolean(body.order.paid)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Boolean(true));
+
+    let result = engine.evaluate("body.order.note", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Blank);
+
+    let result = engine.evaluate("Text(First(Table(body.lines)).Value.sku)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("X".into()));
+
+    let result = engine.evaluate("Value(body.order.id)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("Table(body.order)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("ParseJSON(\"{\")", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+}
+
+#[test]
+fn json_serialization() {
+    let engine = PowerFxEngine::new();
+
+    let mut session = Session::new();
+    session.set_variable("people", DataValue::Table(vec![
+        Record::from(vec![("Name".into(), DataValue::Text("Foo".into())), ("Born".into(), DataValue::Date(NaiveDate::from_ymd_opt(1990, 5, 17).unwrap()))]),
+        Record::from(vec![("Name".into(), DataValue::Blank), ("Born".into(), DataValue::Blank)]),
+    ].into()));
+    session.set_variable("photo", DataValue::Record(Record::from(vec![
+        ("Id".into(), DataValue::Number(1.0)),
+        ("Picture".into(), DataValue::Image("https://example.com/photo.png".into())),
+    ])));
+
+    let result = engine.evaluate("JSON(people)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text(r#"[{"Born":"1990-05-17","Name":"Foo"},{"Born":null,"Name":null}]"#.into()));
+
+    let result = engine.evaluate("JSON({b: 2, a: {c: true}}, JSONFormat.IndentFour)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("{\n    \"a\": {\n        \"c\": true\n    },\n    \"b\": 2\n}".into()));
 
+    session.set_variable("note", DataValue::Text("first\n  second".into()));
+    let result = engine.evaluate("JSON({note: note}, JSONFormat.IndentFour)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("{\n    \"note\": \"first\\n  second\"\n}".into()));
+
+    let result = engine.evaluate("JSON(photo)", Some(&mut session));
+    assert!(matches!(result, Err(EvaluationError::InvalidArgument(_))));
+
+    let result = engine.evaluate("JSON(photo, JSONFormat.IgnoreBinaryData)", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text(r#"{"Id":1}"#.into()));
+
+    let result = engine.evaluate("JSON(ParseJSON(\"[1, 2]\"))", Some(&mut session)).unwrap();
+    assert_eq!(result, DataValue::Text("[1,2]".into()));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_conversion() {
+    use serde::{Deserialize, Serialize};
+    use crate::serialization::{from_data_value, to_data_value};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        name: String,
+        price: f64,
+        quantity: u32,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        note: Option<String>,
+        items: Vec<Item>,
+    }
+
+    let order = Order {
+        id: 7,
+        note: None,
+        items: vec![
+            Item { name: "Foo".to_string(), price: 2.5, quantity: 4 },
+            Item { name: "Bar".to_string(), price: 10.0, quantity: 1 },
+        ],
+    };
+
+    let engine = PowerFxEngine::new();
+    let mut session = Session::new();
+    session.set_variable("order", to_data_value(&order).unwrap());
+    session.set_variable("items", match session.get_variable("order") {
+        Some(DataValue::Record(r)) => r.get("items").cloned().unwrap(),
+        _ => panic!("Expected a record"),
+    });
+
+    let total = engine.evaluate("Sum(items, price * quantity)", Some(&mut session)).unwrap();
+    assert_eq!(total, DataValue::Number(20.0));
+
+    let round_trip: Order = from_data_value(session.get_variable("order").unwrap()).unwrap();
+    assert_eq!(round_trip, order);
+
+    let json = serde_json::to_string(&DataValue::Table(vec![
+        Record::from(vec![("Name".into(), DataValue::Text("Foo".into())), ("Age".into(), DataValue::Number(30.0))]),
+        Record::from(vec![("Name".into(), DataValue::Blank), ("Age".into(), DataValue::Number(4.5))]),
+    ].into())).unwrap();
+    assert_eq!(json, r#"[{"Age":30,"Name":"Foo"},{"Age":4.5,"Name":null}]"#);
+
+    let numbers = vec![1u32, 2, 3];
+    let round_trip: Vec<u32> = from_data_value(&to_data_value(&numbers).unwrap()).unwrap();
+    assert_eq!(round_trip, numbers);
+
+    let names = vec![Some("a".to_string()), None];
+    let round_trip: Vec<Option<String>> = from_data_value(&to_data_value(&names).unwrap()).unwrap();
+    assert_eq!(round_trip, names);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(non_snake_case)]
+    struct Wrapped {
+        Value: u32,
+    }
+
+    let wrapped = vec![Wrapped { Value: 1 }, Wrapped { Value: 2 }];
+    let round_trip: Vec<Wrapped> = from_data_value(&to_data_value(&wrapped).unwrap()).unwrap();
+    assert_eq!(round_trip, wrapped);
+
+    let values: DataValue = serde_json::from_str("[1, 2]").unwrap();
+    assert_eq!(values, DataValue::Table(vec![
+        Record::from(vec![("Value".into(), DataValue::Number(1.0))]),
+        Record::from(vec![("Value".into(), DataValue::Number(2.0))]),
+    ].into()));
+    assert_eq!(serde_json::to_string(&values).unwrap(), "[1,2]");
+
+    engine.register_option_set("Status", vec![("Active", 1, "Active"), ("OnHold", 2, "On Hold")]);
+    let DataValue::OptionSet(status) = engine.evaluate("Status.OnHold", None).unwrap() else { panic!("Expected an option") };
+    assert_eq!(serde_json::to_string(&status).unwrap(), r#""OnHold""#);
+    let error = serde_json::from_str::<crate::models::OptionSet>(r#""OnHold""#).unwrap_err();
+    assert!(error.to_string().contains("OptionSetType::option"));
+    let name: String = serde_json::from_str(&serde_json::to_string(&status).unwrap()).unwrap();
+    assert_eq!(status.option_set().option(&name).as_ref(), Some(&status));
+}
@@ -0,0 +1,107 @@
+use crate::{ast::{self, Expression, Literal}, evaluator::EvaluationError, function_registry::{FunctionKind, FunctionRegistry}};
+
+
+/// The kind of formula being evaluated, which determines what it is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FormulaMode {
+    /// A formula that only computes a value, such as a validation rule. Behavior functions and `;` chaining are rejected.
+    Data,
+    /// A formula that may change state, such as the action of a button. This is the default.
+    #[default]
+    Behavior,
+}
+
+/// Checks parsed formulas against the rules of a `FormulaMode` before they are evaluated.
+pub struct Validator<'a> {
+    function_registry: &'a FunctionRegistry,
+    mode: FormulaMode,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(function_registry: &'a FunctionRegistry, mode: FormulaMode) -> Validator<'a> {
+        Validator {
+            function_registry,
+            mode,
+        }
+    }
+
+    pub fn validate(&self, expressions: &[Expression]) -> Result<(), EvaluationError> {
+        if self.mode == FormulaMode::Behavior {
+            return Ok(());
+        }
+
+        if expressions.len() > 1 {
+            return Err(EvaluationError::ChainingNotAllowed);
+        }
+
+        for expression in expressions {
+            self.validate_expression(expression)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_expression(&self, expression: &Expression) -> Result<(), EvaluationError> {
+        match expression {
+            Expression::UnaryExpression(expression) => match expression {
+                ast::UnaryExpression::Not(e) => self.validate_expression(e),
+                ast::UnaryExpression::Negate(e) => self.validate_expression(e),
+                ast::UnaryExpression::Percent(e) => self.validate_expression(e),
+                ast::UnaryExpression::IsBlank(e) => self.validate_expression(e),
+                ast::UnaryExpression::IsNotBlank(e) => self.validate_expression(e),
+                ast::UnaryExpression::Alias { source, alias: _ } => self.validate_expression(source),
+                ast::UnaryExpression::Member { source, member: _ } => self.validate_expression(source),
+                ast::UnaryExpression::Literal(Literal::Record(r)) => {
+                    for value in r.fields.values() {
+                        self.validate_expression(value)?;
+                    }
+                    Ok(())
+                },
+                ast::UnaryExpression::Literal(Literal::Table(t)) => {
+                    for record in t {
+                        for value in record.fields.values() {
+                            self.validate_expression(value)?;
+                        }
+                    }
+                    Ok(())
+                },
+                ast::UnaryExpression::Literal(_) => Ok(()),
+                ast::UnaryExpression::Property { .. } => Ok(()),
+                ast::UnaryExpression::Parameter(_) => Ok(()),
+                ast::UnaryExpression::Identifier(_) => Ok(()),
+            },
+            Expression::BinaryExpression(expression) => match expression {
+                ast::BinaryExpression::And(a, b) |
+                ast::BinaryExpression::Or(a, b) |
+                ast::BinaryExpression::Eq(a, b) |
+                ast::BinaryExpression::Ne(a, b) |
+                ast::BinaryExpression::Lt(a, b) |
+                ast::BinaryExpression::Le(a, b) |
+                ast::BinaryExpression::Gt(a, b) |
+                ast::BinaryExpression::Ge(a, b) |
+                ast::BinaryExpression::In(a, b, _) |
+                ast::BinaryExpression::Add(a, b) |
+                ast::BinaryExpression::Subtract(a, b) |
+                ast::BinaryExpression::Multiply(a, b) |
+                ast::BinaryExpression::Divide(a, b) |
+                ast::BinaryExpression::Concat(a, b) |
+                ast::BinaryExpression::Exponent(a, b) => {
+                    self.validate_expression(a)?;
+                    self.validate_expression(b)
+                },
+            },
+            Expression::FunctionExpression(func) => {
+                if let Some(metadata) = self.function_registry.get_metadata(&func.name) {
+                    if metadata.signature.kind == FunctionKind::Behavior {
+                        return Err(EvaluationError::BehaviorFunctionNotAllowed(func.name.to_string()));
+                    }
+                }
+
+                for arg in &func.args {
+                    self.validate_expression(arg)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
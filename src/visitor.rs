@@ -0,0 +1,339 @@
+use std::{sync::Arc, collections::BTreeSet};
+
+use super::ast::*;
+
+
+/// Walks an expression tree without changing it. Each `visit_` method walks the node's children by default, so an
+/// implementation only overrides the nodes it is interested in, and calls the matching `walk_` function to carry on
+/// into the children.
+pub trait Visitor {
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_unary_expression(&mut self, expression: &UnaryExpression) {
+        walk_unary_expression(self, expression);
+    }
+
+    fn visit_binary_expression(&mut self, expression: &BinaryExpression) {
+        walk_binary_expression(self, expression);
+    }
+
+    fn visit_function_expression(&mut self, func: &FunctionExpression) {
+        walk_function_expression(self, func);
+    }
+
+    fn visit_literal(&mut self, literal: &Literal) {
+        walk_literal(self, literal);
+    }
+}
+
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::UnaryExpression(expression) => visitor.visit_unary_expression(expression),
+        Expression::BinaryExpression(expression) => visitor.visit_binary_expression(expression),
+        Expression::FunctionExpression(func) => visitor.visit_function_expression(func),
+    }
+}
+
+pub fn walk_unary_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &UnaryExpression) {
+    match expression {
+        UnaryExpression::Not(e) |
+        UnaryExpression::Negate(e) |
+        UnaryExpression::Percent(e) |
+        UnaryExpression::IsBlank(e) |
+        UnaryExpression::IsNotBlank(e) => visitor.visit_expression(e),
+        UnaryExpression::Alias { source, alias: _ } => visitor.visit_expression(source),
+        UnaryExpression::Member { source, member: _ } => visitor.visit_expression(source),
+        UnaryExpression::Literal(literal) => visitor.visit_literal(literal),
+        UnaryExpression::Property { .. } |
+        UnaryExpression::Parameter(_) |
+        UnaryExpression::Identifier(_) => {},
+    }
+}
+
+pub fn walk_binary_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &BinaryExpression) {
+    let (a, b) = operands(expression);
+    visitor.visit_expression(a);
+    visitor.visit_expression(b);
+}
+
+pub fn walk_function_expression<V: Visitor + ?Sized>(visitor: &mut V, func: &FunctionExpression) {
+    for arg in &func.args {
+        visitor.visit_expression(arg);
+    }
+}
+
+pub fn walk_literal<V: Visitor + ?Sized>(visitor: &mut V, literal: &Literal) {
+    match literal {
+        Literal::Record(record) => {
+            for value in record.fields.values() {
+                visitor.visit_expression(value);
+            }
+        },
+        Literal::Table(records) => {
+            for value in records.iter().flat_map(|record| record.fields.values()) {
+                visitor.visit_expression(value);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Walks an expression tree and can change it in place, e.g. to rename an identifier. The default methods walk the
+/// children as in `Visitor`.
+pub trait VisitorMut {
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_unary_expression_mut(&mut self, expression: &mut UnaryExpression) {
+        walk_unary_expression_mut(self, expression);
+    }
+
+    fn visit_binary_expression_mut(&mut self, expression: &mut BinaryExpression) {
+        walk_binary_expression_mut(self, expression);
+    }
+
+    fn visit_function_expression_mut(&mut self, func: &mut FunctionExpression) {
+        walk_function_expression_mut(self, func);
+    }
+
+    fn visit_literal_mut(&mut self, literal: &mut Literal) {
+        walk_literal_mut(self, literal);
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::UnaryExpression(expression) => visitor.visit_unary_expression_mut(expression),
+        Expression::BinaryExpression(expression) => visitor.visit_binary_expression_mut(expression),
+        Expression::FunctionExpression(func) => visitor.visit_function_expression_mut(func),
+    }
+}
+
+pub fn walk_unary_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut UnaryExpression) {
+    match expression {
+        UnaryExpression::Not(e) |
+        UnaryExpression::Negate(e) |
+        UnaryExpression::Percent(e) |
+        UnaryExpression::IsBlank(e) |
+        UnaryExpression::IsNotBlank(e) => visitor.visit_expression_mut(e),
+        UnaryExpression::Alias { source, alias: _ } => visitor.visit_expression_mut(source),
+        UnaryExpression::Member { source, member: _ } => visitor.visit_expression_mut(source),
+        UnaryExpression::Literal(literal) => visitor.visit_literal_mut(literal),
+        UnaryExpression::Property { .. } |
+        UnaryExpression::Parameter(_) |
+        UnaryExpression::Identifier(_) => {},
+    }
+}
+
+pub fn walk_binary_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut BinaryExpression) {
+    let (a, b) = operands_mut(expression);
+    visitor.visit_expression_mut(a);
+    visitor.visit_expression_mut(b);
+}
+
+pub fn walk_function_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, func: &mut FunctionExpression) {
+    for arg in &mut func.args {
+        visitor.visit_expression_mut(arg);
+    }
+}
+
+pub fn walk_literal_mut<V: VisitorMut + ?Sized>(visitor: &mut V, literal: &mut Literal) {
+    match literal {
+        Literal::Record(record) => {
+            for value in record.fields.values_mut() {
+                visitor.visit_expression_mut(value);
+            }
+        },
+        Literal::Table(records) => {
+            for value in records.iter_mut().flat_map(|record| record.fields.values_mut()) {
+                visitor.visit_expression_mut(value);
+            }
+        },
+        _ => {},
+    }
+}
+
+fn operands(expression: &BinaryExpression) -> (&Expression, &Expression) {
+    match expression {
+        BinaryExpression::And(a, b) |
+        BinaryExpression::Or(a, b) |
+        BinaryExpression::Eq(a, b) |
+        BinaryExpression::Ne(a, b) |
+        BinaryExpression::Lt(a, b) |
+        BinaryExpression::Le(a, b) |
+        BinaryExpression::Gt(a, b) |
+        BinaryExpression::Ge(a, b) |
+        BinaryExpression::In(a, b, _) |
+        BinaryExpression::Add(a, b) |
+        BinaryExpression::Subtract(a, b) |
+        BinaryExpression::Multiply(a, b) |
+        BinaryExpression::Divide(a, b) |
+        BinaryExpression::Concat(a, b) |
+        BinaryExpression::Exponent(a, b) => (a, b),
+    }
+}
+
+fn operands_mut(expression: &mut BinaryExpression) -> (&mut Expression, &mut Expression) {
+    match expression {
+        BinaryExpression::And(a, b) |
+        BinaryExpression::Or(a, b) |
+        BinaryExpression::Eq(a, b) |
+        BinaryExpression::Ne(a, b) |
+        BinaryExpression::Lt(a, b) |
+        BinaryExpression::Le(a, b) |
+        BinaryExpression::Gt(a, b) |
+        BinaryExpression::Ge(a, b) |
+        BinaryExpression::In(a, b, _) |
+        BinaryExpression::Add(a, b) |
+        BinaryExpression::Subtract(a, b) |
+        BinaryExpression::Multiply(a, b) |
+        BinaryExpression::Divide(a, b) |
+        BinaryExpression::Concat(a, b) |
+        BinaryExpression::Exponent(a, b) => (a, b),
+    }
+}
+
+
+/// The names of the variables and fields that the expression refers to, including fields reached through
+/// `ThisRecord`, `ThisItem`, `Parent` and `Self`.
+pub fn referenced_identifiers(expression: &Expression) -> BTreeSet<Arc<str>> {
+    struct Identifiers(BTreeSet<Arc<str>>);
+
+    impl Visitor for Identifiers {
+        fn visit_unary_expression(&mut self, expression: &UnaryExpression) {
+            match expression {
+                UnaryExpression::Property { context: _, key } => { self.0.insert(key.clone()); },
+                UnaryExpression::Parameter(name) |
+                UnaryExpression::Identifier(name) => { self.0.insert(name.clone()); },
+                _ => walk_unary_expression(self, expression),
+            }
+        }
+    }
+
+    let mut identifiers = Identifiers(BTreeSet::new());
+    identifiers.visit_expression(expression);
+    identifiers.0
+}
+
+/// The names of the functions that the expression calls, as they are written in the formula.
+pub fn called_functions(expression: &Expression) -> BTreeSet<Arc<str>> {
+    struct Functions(BTreeSet<Arc<str>>);
+
+    impl Visitor for Functions {
+        fn visit_function_expression(&mut self, func: &FunctionExpression) {
+            self.0.insert(func.name.clone());
+            walk_function_expression(self, func);
+        }
+    }
+
+    let mut functions = Functions(BTreeSet::new());
+    functions.visit_expression(expression);
+    functions.0
+}
+
+/// Options for `rename_identifier`. `PowerFxEngine::rename_options` gives the options that match an engine.
+#[derive(Debug, Clone, Default)]
+pub struct RenameOptions {
+    /// Only rename names written with the same casing as `from`. By default names are matched without regard to case.
+    pub strict_names: bool,
+    /// The names of the enums and option sets that formulas can refer to. Their members, e.g. `Status.Price`, are
+    /// not renamed.
+    pub type_names: BTreeSet<Arc<str>>,
+}
+
+/// Renames the variable or field `from` to `to` wherever the expression refers to it: as a name on its own, through
+/// `ThisRecord` and the other contexts, and as a field reached with `.`, e.g. `First(Orders).Total`.
+///
+/// Names that a formula binds itself are left alone: record fields are not renamed, and neither are references to the
+/// fields of a `With` record, such as `Price` in `With({ Price: 5 }, Price * 2)`. Members of record literals, enums and
+/// option sets are not renamed either. Returns how many references were renamed.
+pub fn rename_identifier(expression: &mut Expression, from: &str, to: &str, options: &RenameOptions) -> usize {
+    struct Rename<'a> {
+        from: &'a str,
+        lowercase_from: String,
+        to: Arc<str>,
+        options: &'a RenameOptions,
+        /// Whether the expression being walked is in the scope of a name bound by the formula that hides `from`.
+        shadowed: bool,
+        count: usize,
+    }
+
+    impl Rename<'_> {
+        fn matches(&self, name: &str) -> bool {
+            match self.options.strict_names {
+                true => name == self.from,
+                false => name.to_lowercase() == self.lowercase_from,
+            }
+        }
+
+        fn rename(&mut self, name: &mut Arc<str>) {
+            if self.matches(name) {
+                *name = self.to.clone();
+                self.count += 1;
+            }
+        }
+
+        /// Whether the argument at `index` of `func` binds `from` for the arguments after it, as a field of the record
+        /// passed to `With` does.
+        fn binds(&self, func: &FunctionExpression, index: usize) -> bool {
+            match &func.args[index] {
+                Expression::UnaryExpression(UnaryExpression::Literal(Literal::Record(record))) => {
+                    let with = match self.options.strict_names {
+                        true => func.name.as_ref() == "With",
+                        false => func.name.eq_ignore_ascii_case("With"),
+                    };
+                    index == 0 && with && record.fields.keys().any(|key| self.matches(key))
+                },
+                _ => false,
+            }
+        }
+
+        /// Whether `member` of `source` is a member of a record literal, an enum or an option set, rather than a field
+        /// of a record or table that the formula reads.
+        fn is_fixed_member(&self, source: &Expression) -> bool {
+            match source {
+                Expression::UnaryExpression(UnaryExpression::Literal(Literal::Record(_))) => true,
+                Expression::UnaryExpression(UnaryExpression::Identifier(name)) => self.options.type_names.contains(name),
+                _ => false,
+            }
+        }
+    }
+
+    impl VisitorMut for Rename<'_> {
+        fn visit_unary_expression_mut(&mut self, expression: &mut UnaryExpression) {
+            match expression {
+                UnaryExpression::Property { context: Context::Parent, key: name } => self.rename(name),
+                UnaryExpression::Property { context: _, key: name } |
+                UnaryExpression::Parameter(name) |
+                UnaryExpression::Identifier(name) => {
+                    if !self.shadowed {
+                        self.rename(name);
+                    }
+                },
+                UnaryExpression::Member { source, member } => {
+                    if !self.is_fixed_member(source) {
+                        self.rename(member);
+                    }
+                    walk_unary_expression_mut(self, expression);
+                },
+                _ => walk_unary_expression_mut(self, expression),
+            }
+        }
+
+        fn visit_function_expression_mut(&mut self, func: &mut FunctionExpression) {
+            let outer = self.shadowed;
+            for index in 0..func.args.len() {
+                self.visit_expression_mut(&mut func.args[index]);
+                self.shadowed |= self.binds(func, index);
+            }
+            self.shadowed = outer;
+        }
+    }
+
+    let mut rename = Rename { from, lowercase_from: from.to_lowercase(), to: Arc::from(to), options, shadowed: false, count: 0 };
+    rename.visit_expression_mut(expression);
+    rename.count
+}